@@ -1,6 +1,82 @@
+use ripemd::Ripemd160;
 use serde::{Deserialize, Serialize}; //Imported to enable conversion of data structures.
+use sha2::{Digest, Sha256};
+use siphasher::sip::SipHasher24;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt; //To enable formatting
-use std::ops::Deref;
+use std::hash::Hasher;
+use std::ops::{Deref, Range};
+
+pub const MAX_MONEY: u64 = 21_000_000 * 100_000_000;
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+pub const TRUC_MAX_VSIZE: usize = 10_000;
+pub const TRUC_CHILD_MAX_VSIZE: usize = 1_000;
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
+pub const DUST_RELAY_FEE_RATE: u64 = 3;
+pub const DEFAULT_ANCESTOR_LIMIT: usize = 25;
+pub const DEFAULT_ANCESTOR_SIZE_LIMIT_VSIZE: usize = 101_000;
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+pub const DEFAULT_BYTES_PER_SIGOP: usize = 20;
+pub const SIGHASH_ALL: u32 = 0x01;
+pub const SIGHASH_NONE: u32 = 0x02;
+pub const SIGHASH_SINGLE: u32 = 0x03;
+pub const SIGHASH_ANYONECANPAY: u32 = 0x80;
+pub const SIZE_BUCKETS: [usize; 4] = [0, 250, 500, 1000];
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+pub fn hash256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(Sha256::digest(data)).into()
+}
+
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    Ripemd160::digest(Sha256::digest(data)).into()
+}
+
+trait ByteSink {
+    fn put(&mut self, bytes: &[u8]);
+}
+
+impl ByteSink for Vec<u8> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+// Callers must check the destination is large enough before encoding into it.
+struct SliceSink<'a> {
+    buf: &'a mut [u8],
+    written: usize,
+}
+
+impl ByteSink for SliceSink<'_> {
+    fn put(&mut self, bytes: &[u8]) {
+        let end = self.written + bytes.len();
+        self.buf[self.written..end].copy_from_slice(bytes);
+        self.written = end;
+    }
+}
+
+// Runs a sub-parser and rejects a consumed length that runs past its input. A
+// parser bug like that is caught loudly in debug builds and as InvalidFormat in release.
+fn parse_within<T>(
+    bytes: &[u8],
+    parse: impl Fn(&[u8]) -> Result<(T, usize), BitcoinError>,
+) -> Result<(T, usize), BitcoinError> {
+    let (value, consumed) = parse(bytes)?;
+    debug_assert!(
+        consumed <= bytes.len(),
+        "sub-parser consumed {consumed} of {} bytes",
+        bytes.len()
+    );
+    if consumed > bytes.len() {
+        return Err(BitcoinError::InvalidFormat);
+    }
+    Ok((value, consumed))
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct CompactSize {
@@ -11,6 +87,11 @@ pub struct CompactSize {
 pub enum BitcoinError {
     InsufficientBytes,
     InvalidFormat,
+    MissingPrevout,
+    InsufficientFunds,
+    IndexOutOfBounds,
+    ValueOutOfRange,
+    LimitExceeded,
 }
 
 impl CompactSize {
@@ -18,57 +99,94 @@ impl CompactSize {
         CompactSize { value }
     }
 
+    pub fn encoded_len(value: u64) -> usize {
+        if value < 0xFD {
+            1
+        } else if value <= 0xFFFF {
+            3
+        } else if value <= 0xFFFFFFFF {
+            5
+        } else {
+            9
+        }
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(CompactSize::encoded_len(self.value));
+        self.write_to(&mut bytes);
+        debug_assert!(CompactSize::is_minimal_encoding(&bytes));
+        bytes
+    }
+
+    // True when `bytes` is exactly one CompactSize in its shortest form.
+    pub fn is_minimal_encoding(bytes: &[u8]) -> bool {
+        match Self::decode(bytes, true) {
+            Ok((size, len)) => len == bytes.len() && len == CompactSize::encoded_len(size.value),
+            Err(_) => false,
+        }
+    }
+
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        self.encode(out);
+    }
+
+    fn encode(&self, out: &mut impl ByteSink) {
         if self.value < 0xFD {
-            vec![self.value as u8]
+            out.put(&[self.value as u8]);
         } else if self.value <= 0xFFFF {
-            let mut bytes = vec![0xFD];
-            bytes.extend_from_slice(&(self.value as u16).to_le_bytes());
-            bytes
+            out.put(&[0xFD]);
+            out.put(&(self.value as u16).to_le_bytes());
         } else if self.value <= 0xFFFFFFFF {
-            let mut bytes = vec![0xFE];
-            bytes.extend_from_slice(&(self.value as u32).to_le_bytes());
-            bytes
+            out.put(&[0xFE]);
+            out.put(&(self.value as u32).to_le_bytes());
         } else {
-            let mut bytes = vec![0xFF];
-            bytes.extend_from_slice(&self.value.to_le_bytes());
-            bytes
+            out.put(&[0xFF]);
+            out.put(&self.value.to_le_bytes());
         }
     }
 
+    // Rejects non-minimal encodings such as 0xFD 0x05 0x00 with InvalidFormat.
     pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        Self::decode(bytes, false)
+    }
+
+    fn decode(bytes: &[u8], lenient: bool) -> Result<(Self, usize), BitcoinError> {
         if bytes.is_empty() {
             return Err(BitcoinError::InsufficientBytes);
         }
         let prefix = bytes[0];
-        match prefix {
-            0x00..=0xFC => Ok((CompactSize::new(prefix as u64), 1)),
+        let (value, len) = match prefix {
+            0x00..=0xFC => (prefix as u64, 1),
             0xFD => {
                 if bytes.len() < 3 {
                     return Err(BitcoinError::InsufficientBytes);
                 }
                 let value = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
-                Ok((CompactSize::new(value as u64), 3))
+                (value as u64, 3)
             }
             0xFE => {
                 if bytes.len() < 5 {
                     return Err(BitcoinError::InsufficientBytes);
                 }
                 let value = u32::from_le_bytes(bytes[1..5].try_into().unwrap());
-                Ok((CompactSize::new(value as u64), 5))
+                (value as u64, 5)
             }
             0xFF => {
                 if bytes.len() < 9 {
                     return Err(BitcoinError::InsufficientBytes);
                 }
                 let value = u64::from_le_bytes(bytes[1..9].try_into().unwrap());
-                Ok((CompactSize::new(value), 9))
+                (value, 9)
             }
+        };
+        if !lenient && len != CompactSize::encoded_len(value) {
+            return Err(BitcoinError::InvalidFormat);
         }
+        Ok((CompactSize::new(value), len))
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash, PartialOrd, Ord)]
 pub struct Txid(pub [u8; 32]);
 
 impl Serialize for Txid {
@@ -76,7 +194,11 @@ impl Serialize for Txid {
     where
         S: serde::Serializer,
     {
-        serializer.serialize_str(&hex::encode(self.0))
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode(self.0))
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
     }
 }
 
@@ -85,18 +207,70 @@ impl<'de> Deserialize<'de> for Txid {
     where
         D: serde::Deserializer<'de>,
     {
-        let s = String::deserialize(deserializer)?;
-        let bytes = hex::decode(s).map_err(serde::de::Error::custom)?;
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            let bytes = hex::decode(s).map_err(serde::de::Error::custom)?;
+            Txid::from_slice(&bytes).map_err(serde::de::Error::custom)
+        } else {
+            deserializer.deserialize_bytes(TxidBytesVisitor)
+        }
+    }
+}
+
+impl Txid {
+    // Hex in reversed (big-endian) byte order, as shown by block explorers and bitcoind.
+    // Serialize keeps the internal little-endian order.
+    pub fn to_display_string(&self) -> String {
+        let mut bytes = self.0;
+        bytes.reverse();
+        hex::encode(bytes)
+    }
+
+    // First 8 bytes in internal (not display) order, read as a little-endian u64.
+    pub fn prefix8(&self) -> u64 {
+        u64::from_le_bytes(self.0[0..8].try_into().unwrap())
+    }
+
+    fn from_slice(bytes: &[u8]) -> Result<Self, &'static str> {
         if bytes.len() != 32 {
-            return Err(serde::de::Error::custom("invalid length"));
+            return Err("invalid length");
         }
         let mut array = [0u8; 32];
-        array.copy_from_slice(&bytes);
+        array.copy_from_slice(bytes);
         Ok(Txid(array))
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+struct TxidBytesVisitor;
+
+impl<'de> serde::de::Visitor<'de> for TxidBytesVisitor {
+    type Value = Txid;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("32 txid bytes")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Txid::from_slice(v).map_err(E::custom)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(32);
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+        Txid::from_slice(&bytes).map_err(serde::de::Error::custom)
+    }
+}
+
+// Ordered by txid bytes (internal order), then vout.
+#[derive(Debug, PartialEq, Eq, Clone, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct OutPoint {
     pub txid: Txid,
     pub vout: u32,
@@ -111,11 +285,20 @@ impl OutPoint {
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = self.txid.0.to_vec();
-        bytes.extend_from_slice(&self.vout.to_le_bytes());
+        let mut bytes = Vec::with_capacity(36);
+        self.write_to(&mut bytes);
         bytes
     }
 
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        self.encode(out);
+    }
+
+    fn encode(&self, out: &mut impl ByteSink) {
+        out.put(&self.txid.0);
+        out.put(&self.vout.to_le_bytes());
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), BitcoinError> {
         if bytes.len() < 36 {
             return Err(BitcoinError::InsufficientBytes);
@@ -127,7 +310,7 @@ impl OutPoint {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
 pub struct Script {
     pub bytes: Vec<u8>,
 }
@@ -137,21 +320,434 @@ impl Script {
         Script { bytes }
     }
 
+    pub fn serialized_len(&self) -> usize {
+        CompactSize::encoded_len(self.bytes.len() as u64) + self.bytes.len()
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = CompactSize::new(self.bytes.len() as u64).to_bytes();
-        bytes.extend_from_slice(&self.bytes);
+        let mut bytes = Vec::with_capacity(self.serialized_len());
+        self.write_to(&mut bytes);
         bytes
     }
 
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        self.encode(out);
+    }
+
+    fn encode(&self, out: &mut impl ByteSink) {
+        CompactSize::new(self.bytes.len() as u64).encode(out);
+        out.put(&self.bytes);
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), BitcoinError> {
-        let (compact_size, size_len) = CompactSize::from_bytes(bytes)?;
-        let script_len = compact_size.value as usize;
-        if bytes.len() < size_len + script_len {
+        Self::decode(bytes, false)
+    }
+
+    fn decode(bytes: &[u8], lenient: bool) -> Result<(Self, usize), BitcoinError> {
+        let (compact_size, size_len) = parse_within(bytes, |b| CompactSize::decode(b, lenient))?;
+        // Compare in u64 so a huge claimed length can neither wrap nor allocate.
+        if compact_size.value > (bytes.len() - size_len) as u64 {
             return Err(BitcoinError::InsufficientBytes);
         }
+        let script_len = compact_size.value as usize;
         let script_bytes = bytes[size_len..size_len + script_len].to_vec();
         Ok((Script::new(script_bytes), size_len + script_len))
     }
+
+    pub fn push_opcode(&mut self, opcode: u8) {
+        self.bytes.push(opcode);
+    }
+
+    // Uses the smallest push opcode able to carry `data`.
+    pub fn push_data(&mut self, data: &[u8]) {
+        let len = data.len();
+        if len < 0x4C {
+            self.bytes.push(len as u8);
+        } else if len <= 0xFF {
+            self.bytes.push(0x4C);
+            self.bytes.push(len as u8);
+        } else if len <= 0xFFFF {
+            self.bytes.push(0x4D);
+            self.bytes.extend_from_slice(&(len as u16).to_le_bytes());
+        } else {
+            self.bytes.push(0x4E);
+            self.bytes.extend_from_slice(&(len as u32).to_le_bytes());
+        }
+        self.bytes.extend_from_slice(data);
+    }
+
+    // Small integers use OP_0, OP_1NEGATE and OP_1..OP_16; anything else is pushed
+    // as a minimally encoded script number.
+    pub fn push_int(&mut self, n: i64) {
+        match n {
+            0 => self.push_opcode(0x00),
+            -1 => self.push_opcode(0x4F),
+            1..=16 => self.push_opcode(0x50 + n as u8),
+            _ => {
+                let mut abs = n.unsigned_abs();
+                let mut encoded = Vec::new();
+                while abs > 0 {
+                    encoded.push((abs & 0xFF) as u8);
+                    abs >>= 8;
+                }
+                if encoded.last().unwrap() & 0x80 != 0 {
+                    encoded.push(if n < 0 { 0x80 } else { 0x00 });
+                } else if n < 0 {
+                    *encoded.last_mut().unwrap() |= 0x80;
+                }
+                self.push_data(&encoded);
+            }
+        }
+    }
+
+    pub fn to_p2sh(&self) -> Script {
+        let mut script = Script::new(Vec::with_capacity(23));
+        script.push_opcode(0xA9);
+        script.push_data(&hash160(&self.bytes));
+        script.push_opcode(0x87);
+        script
+    }
+
+    pub fn to_p2wsh(&self) -> Script {
+        let mut script = Script::new(Vec::with_capacity(34));
+        script.push_int(0);
+        script.push_data(&sha256(&self.bytes));
+        script
+    }
+
+    // Positions of push opcodes that a shorter opcode could have expressed (BIP62 rule 3).
+    fn non_minimal_push_positions(&self) -> Vec<usize> {
+        let mut positions = Vec::new();
+        let mut instructions = self.instructions();
+        loop {
+            let position = instructions.cursor;
+            let Some(Ok(instruction)) = instructions.next() else {
+                break;
+            };
+            let Instruction::PushBytes(data) = instruction else {
+                continue;
+            };
+            let opcode = self.bytes[position];
+            let minimal = match data {
+                [] => opcode == 0x00,
+                [1..=16] | [0x81] => false,
+                _ if data.len() < 0x4C => opcode as usize == data.len(),
+                _ if data.len() <= 0xFF => opcode == 0x4C,
+                _ if data.len() <= 0xFFFF => opcode == 0x4D,
+                _ => true,
+            };
+            if !minimal {
+                positions.push(position);
+            }
+        }
+        positions
+    }
+
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions {
+            bytes: &self.bytes,
+            cursor: 0,
+        }
+    }
+
+    pub fn pushed_data(&self) -> Vec<&[u8]> {
+        self.instructions()
+            .map_while(Result::ok)
+            .filter_map(|instruction| match instruction {
+                Instruction::PushBytes(data) => Some(data),
+                Instruction::Op(_) => None,
+            })
+            .collect()
+    }
+
+    // Structural SEC prefix check on every 33- and 65-byte push; no curve validation.
+    pub fn has_valid_pubkey_encodings(&self) -> bool {
+        self.pushed_data().iter().all(|data| match data.len() {
+            33 => matches!(data[0], 0x02 | 0x03),
+            65 => data[0] == 0x04,
+            _ => true,
+        })
+    }
+
+    pub fn is_provably_unspendable(&self) -> bool {
+        self.bytes.first() == Some(&0x6A) || self.bytes.len() > MAX_SCRIPT_SIZE
+    }
+
+    // Payload of the push directly following a leading OP_RETURN.
+    pub fn op_return_data(&self) -> Option<&[u8]> {
+        if self.bytes.first() != Some(&0x6A) {
+            return None;
+        }
+        let mut instructions = Instructions {
+            bytes: &self.bytes,
+            cursor: 1,
+        };
+        match instructions.next()? {
+            Ok(Instruction::PushBytes(data)) => Some(data),
+            _ => None,
+        }
+    }
+
+    // Bytes after `prefix` in the OP_RETURN payload, e.g. the hash in a
+    // BIP141 witness commitment when `prefix` is aa21a9ed.
+    pub fn op_return_commitment(&self, prefix: &[u8]) -> Option<&[u8]> {
+        self.op_return_data()?.strip_prefix(prefix)
+    }
+
+    // Runes scripts start OP_RETURN OP_13 and carry their payload in the following
+    // pushes; Omni puts the "omni" magic at the start of the OP_RETURN push.
+    pub fn metaprotocol_marker(&self) -> Option<Metaprotocol> {
+        if self.bytes.starts_with(&[0x6A, 0x5D]) {
+            let instructions = Instructions {
+                bytes: &self.bytes,
+                cursor: 2,
+            };
+            let payload = instructions
+                .map_while(|instruction| match instruction {
+                    Ok(Instruction::PushBytes(data)) => Some(data),
+                    _ => None,
+                })
+                .flatten()
+                .copied()
+                .collect();
+            return Some(Metaprotocol::Runes(payload));
+        }
+        if let Some(payload) = self.op_return_commitment(b"omni") {
+            return Some(Metaprotocol::Omni(payload.to_vec()));
+        }
+        None
+    }
+
+    // Rough worst-case stack depth reached while running this script on its own.
+    // Only common opcodes are modelled; this is an estimate, not an interpreter.
+    pub fn max_stack_estimate(&self) -> usize {
+        let mut depth = 0usize;
+        let mut max_depth = 0usize;
+        let mut last_small_int = None;
+        for instruction in self.instructions().map_while(Result::ok) {
+            let mut small_int = None;
+            match instruction {
+                Instruction::PushBytes(_) => depth += 1,
+                Instruction::Op(op @ 0x51..=0x60) => {
+                    depth += 1;
+                    small_int = Some((op - 0x50) as usize);
+                }
+                Instruction::Op(0x4F | 0x76 | 0x78 | 0x82) => depth += 1,
+                Instruction::Op(0x6E) => depth += 2,
+                Instruction::Op(0x6F) => depth += 3,
+                Instruction::Op(0x69 | 0x75 | 0x87 | 0xAC | 0x93 | 0x94) => {
+                    depth = depth.saturating_sub(1)
+                }
+                Instruction::Op(0x6D | 0x88 | 0xAD) => depth = depth.saturating_sub(2),
+                Instruction::Op(0xAE | 0xAF) => {
+                    // Pops the key count, the keys, and (unseen here) the signatures.
+                    let keys = last_small_int.unwrap_or(0);
+                    depth = depth.saturating_sub(keys + 2);
+                    if instruction == Instruction::Op(0xAE) {
+                        depth += 1;
+                    }
+                }
+                Instruction::Op(_) => {}
+            }
+            max_depth = max_depth.max(depth);
+            last_small_int = small_int;
+        }
+        max_depth
+    }
+
+    // Core's GetSigOpCount. Inaccurate counting charges every CHECKMULTISIG 20 sigops;
+    // accurate counting (used for P2SH redeem scripts) uses the preceding OP_N.
+    // Counting stops at the first malformed push.
+    pub fn sigop_count(&self, accurate: bool) -> usize {
+        let mut count = 0;
+        let mut last_op = None;
+        for instruction in self.instructions().map_while(Result::ok) {
+            match instruction {
+                Instruction::Op(0xAC | 0xAD) => count += 1,
+                Instruction::Op(0xAE | 0xAF) => match last_op {
+                    Some(op @ 0x51..=0x60) if accurate => count += (op - 0x50) as usize,
+                    _ => count += 20,
+                },
+                _ => {}
+            }
+            last_op = match instruction {
+                Instruction::Op(op) => Some(op),
+                Instruction::PushBytes(_) => None,
+            };
+        }
+        count
+    }
+
+    // The last push of a push-only scriptSig, which is the redeem script of a P2SH spend.
+    fn p2sh_redeem_script(&self) -> Option<Script> {
+        let mut redeem_script = None;
+        for instruction in self.instructions() {
+            redeem_script = match instruction {
+                Ok(Instruction::PushBytes(data)) => Some(data),
+                Ok(Instruction::Op(op)) if op <= 0x60 => None,
+                _ => return None,
+            };
+        }
+        redeem_script.map(|data| Script::new(data.to_vec()))
+    }
+
+    // Returns (version, program) for a BIP141 witness program output.
+    pub fn witness_program(&self) -> Option<(u8, &[u8])> {
+        let bytes = &self.bytes;
+        if bytes.len() < 4 || bytes.len() > 42 || bytes[1] as usize != bytes.len() - 2 {
+            return None;
+        }
+        match bytes[0] {
+            0x00 => Some((0, &bytes[2..])),
+            0x51..=0x60 => Some((bytes[0] - 0x50, &bytes[2..])),
+            _ => None,
+        }
+    }
+
+    pub fn has_standard_witness_program_size(&self) -> bool {
+        match self.witness_program() {
+            Some((0, program)) => program.len() == 20 || program.len() == 32,
+            Some((1, program)) => program.len() == 32,
+            _ => false,
+        }
+    }
+
+    pub fn script_type(&self) -> ScriptType {
+        let b = &self.bytes;
+        match b.len() {
+            25 if b[..3] == [0x76, 0xA9, 0x14] && b[23..] == [0x88, 0xAC] => {
+                return ScriptType::P2pkh;
+            }
+            23 if b[..2] == [0xA9, 0x14] && b[22] == 0x87 => return ScriptType::P2sh,
+            35 if b[0] == 0x21 && b[34] == 0xAC => return ScriptType::P2pk,
+            67 if b[0] == 0x41 && b[66] == 0xAC => return ScriptType::P2pk,
+            _ => {}
+        }
+        if b.first() == Some(&0x6A) {
+            return ScriptType::OpReturn;
+        }
+        match self.witness_program() {
+            Some((0, program)) if program.len() == 20 => return ScriptType::P2wpkh,
+            Some((0, program)) if program.len() == 32 => return ScriptType::P2wsh,
+            Some((1, program)) if program.len() == 32 => return ScriptType::P2tr,
+            Some(_) => return ScriptType::WitnessUnknown,
+            None => {}
+        }
+        if self.is_multisig() {
+            return ScriptType::Multisig;
+        }
+        ScriptType::NonStandard
+    }
+
+    fn is_multisig(&self) -> bool {
+        let Ok(instructions) = self.instructions().collect::<Result<Vec<_>, _>>() else {
+            return false;
+        };
+        let [
+            Instruction::Op(m),
+            keys @ ..,
+            Instruction::Op(n),
+            Instruction::Op(0xAE),
+        ] = instructions.as_slice()
+        else {
+            return false;
+        };
+        let small_int = 0x51..=0x60;
+        small_int.contains(m)
+            && small_int.contains(n)
+            && m <= n
+            && keys.len() == (n - 0x50) as usize
+            && keys.iter().all(
+                |key| matches!(key, Instruction::PushBytes(k) if k.len() == 33 || k.len() == 65),
+            )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, PartialOrd, Ord)]
+pub enum ScriptType {
+    P2pk,
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    WitnessUnknown,
+    Multisig,
+    OpReturn,
+    NonStandard,
+}
+
+impl ScriptType {
+    // Typical vsize of an input spending this output type, assuming compressed keys,
+    // P2SH-wrapped P2WPKH for P2sh and 2-of-3 multisig for P2wsh.
+    pub fn input_vsize(&self) -> Option<usize> {
+        match self {
+            ScriptType::P2pk => Some(114),
+            ScriptType::P2pkh => Some(148),
+            ScriptType::P2sh => Some(91),
+            ScriptType::P2wpkh => Some(68),
+            ScriptType::P2wsh => Some(104),
+            ScriptType::P2tr => Some(58),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Metaprotocol {
+    Runes(Vec<u8>),
+    Omni(Vec<u8>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Instruction<'a> {
+    PushBytes(&'a [u8]),
+    Op(u8),
+}
+
+pub struct Instructions<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+}
+
+impl Instructions<'_> {
+    fn read_len(&mut self, width: usize) -> Option<usize> {
+        let end = self.cursor.checked_add(width)?;
+        let len_bytes = self.bytes.get(self.cursor..end)?;
+        self.cursor = end;
+        let mut buf = [0u8; 8];
+        buf[..width].copy_from_slice(len_bytes);
+        usize::try_from(u64::from_le_bytes(buf)).ok()
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<Instruction<'a>, BitcoinError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let opcode = *self.bytes.get(self.cursor)?;
+        self.cursor += 1;
+        let len = match opcode {
+            0x00..=0x4B => Some(opcode as usize),
+            0x4C => self.read_len(1),
+            0x4D => self.read_len(2),
+            0x4E => self.read_len(4),
+            _ => return Some(Ok(Instruction::Op(opcode))),
+        };
+        let data = len
+            .and_then(|len| self.cursor.checked_add(len))
+            .and_then(|end| self.bytes.get(self.cursor..end));
+        match data {
+            Some(data) => {
+                self.cursor += data.len();
+                Some(Ok(Instruction::PushBytes(data)))
+            }
+            None => {
+                self.cursor = self.bytes.len();
+                Some(Err(BitcoinError::InsufficientBytes))
+            }
+        }
+    }
 }
 
 impl Deref for Script {
@@ -182,18 +778,55 @@ impl TransactionOutput {
         }
     }
 
+    pub fn serialized_len(&self) -> usize {
+        8 + self.script_pubkey.serialized_len()
+    }
+
+    // Bitcoin Core's dust rule: worth less than the fee, at DUST_RELAY_FEE_RATE, of
+    // creating and later spending it (148 vbytes of input, 67 for witness programs).
+    pub fn is_dust(&self) -> bool {
+        self.is_dust_at(DUST_RELAY_FEE_RATE as f64)
+    }
+
+    pub fn is_dust_at(&self, fee_rate: f64) -> bool {
+        if self.script_pubkey.is_provably_unspendable() {
+            return false;
+        }
+        let spend_size = if self.script_pubkey.witness_program().is_some() {
+            67
+        } else {
+            148
+        };
+        let threshold = ((self.serialized_len() + spend_size) as f64 * fee_rate).ceil();
+        (self.value as f64) < threshold
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = self.value.to_le_bytes().to_vec();
-        bytes.extend_from_slice(&self.script_pubkey.to_bytes());
+        let mut bytes = Vec::with_capacity(self.serialized_len());
+        self.write_to(&mut bytes);
         bytes
     }
 
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        self.encode(out);
+    }
+
+    fn encode(&self, out: &mut impl ByteSink) {
+        out.put(&self.value.to_le_bytes());
+        self.script_pubkey.encode(out);
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        Self::decode(bytes, false)
+    }
+
+    fn decode(bytes: &[u8], lenient: bool) -> Result<(Self, usize), BitcoinError> {
         if bytes.len() < 8 {
             return Err(BitcoinError::InsufficientBytes);
         }
         let value = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
-        let (script_pubkey, script_len) = Script::from_bytes(&bytes[8..])?;
+        let (script_pubkey, script_len) =
+            parse_within(&bytes[8..], |b| Script::decode(b, lenient))?;
         let total_len = 8 + script_len;
         Ok((TransactionOutput::new(value, script_pubkey), total_len))
     }
@@ -208,16 +841,34 @@ impl TransactionInput {
         }
     }
 
+    pub fn serialized_len(&self) -> usize {
+        36 + self.script_sig.serialized_len() + 4
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = self.previous_output.to_bytes();
-        bytes.extend_from_slice(&self.script_sig.to_bytes());
-        bytes.extend_from_slice(&self.sequence.to_le_bytes());
+        let mut bytes = Vec::with_capacity(self.serialized_len());
+        self.write_to(&mut bytes);
         bytes
     }
 
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        self.encode(out);
+    }
+
+    fn encode(&self, out: &mut impl ByteSink) {
+        self.previous_output.encode(out);
+        self.script_sig.encode(out);
+        out.put(&self.sequence.to_le_bytes());
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), BitcoinError> {
-        let (previous_output, prev_out_len) = OutPoint::from_bytes(bytes)?;
-        let (script_sig, script_sig_len) = Script::from_bytes(&bytes[prev_out_len..])?;
+        Self::decode(bytes, false)
+    }
+
+    fn decode(bytes: &[u8], lenient: bool) -> Result<(Self, usize), BitcoinError> {
+        let (previous_output, prev_out_len) = parse_within(bytes, OutPoint::from_bytes)?;
+        let (script_sig, script_sig_len) =
+            parse_within(&bytes[prev_out_len..], |b| Script::decode(b, lenient))?;
         let sequence_start = prev_out_len + script_sig_len;
         if bytes.len() < sequence_start + 4 {
             return Err(BitcoinError::InsufficientBytes);
@@ -233,6 +884,22 @@ impl TransactionInput {
             total_len,
         ))
     }
+
+    // Guesses the spent output type from the scriptSig alone. Native segwit spends
+    // have an empty scriptSig and cannot be told apart without the witness, so they
+    // return None, as do scriptSigs that match no known pattern.
+    pub fn inferred_spend_type(&self) -> Option<ScriptType> {
+        let pushes = self.script_sig.pushed_data();
+        match pushes.as_slice() {
+            [sig, key] if (9..=73).contains(&sig.len()) && matches!(key.len(), 33 | 65) => {
+                Some(ScriptType::P2pkh)
+            }
+            [redeem] if Script::new(redeem.to_vec()).witness_program().is_some() => {
+                Some(ScriptType::P2sh)
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -243,6 +910,61 @@ pub struct BitcoinTransaction {
     pub lock_time: u32,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LockTime {
+    Height(u32),
+    Time(u32),
+}
+
+// Offsets are byte positions in the buffer handed to from_bytes_with_warnings.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DecodeWarning {
+    NonCanonicalCompactSize { offset: usize },
+    NonMinimalPush { offset: usize },
+}
+
+#[derive(Default)]
+struct ParseTrace {
+    lenient: bool,
+    script_offsets: Vec<Range<usize>>,
+    warnings: Vec<DecodeWarning>,
+}
+
+impl ParseTrace {
+    fn compact_size(&mut self, offset: usize, compact_size: &CompactSize, len: usize) {
+        if len != CompactSize::encoded_len(compact_size.value) {
+            self.warnings
+                .push(DecodeWarning::NonCanonicalCompactSize { offset });
+        }
+    }
+
+    // `prefix_start` is where the script's length prefix begins, `end` where the script ends.
+    fn script(&mut self, prefix_start: usize, script: &Script, end: usize) {
+        let start = end - script.len();
+        self.compact_size(
+            prefix_start,
+            &CompactSize::new(script.len() as u64),
+            start - prefix_start,
+        );
+        for position in script.non_minimal_push_positions() {
+            self.warnings.push(DecodeWarning::NonMinimalPush {
+                offset: start + position,
+            });
+        }
+        self.script_offsets.push(start..end);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct StructureFingerprint {
+    pub input_count: usize,
+    pub output_count: usize,
+    pub output_types: Vec<ScriptType>,
+    pub uses_lock_time: bool,
+    pub signals_rbf: bool,
+    pub bip69_sorted: bool,
+}
+
 impl BitcoinTransaction {
     pub fn new(
         version: u32,
@@ -258,38 +980,132 @@ impl BitcoinTransaction {
         }
     }
 
+    pub fn serialized_len(&self) -> usize {
+        4 + CompactSize::encoded_len(self.inputs.len() as u64)
+            + self
+                .inputs
+                .iter()
+                .map(TransactionInput::serialized_len)
+                .sum::<usize>()
+            + CompactSize::encoded_len(self.outputs.len() as u64)
+            + self
+                .outputs
+                .iter()
+                .map(TransactionOutput::serialized_len)
+                .sum::<usize>()
+            + 4
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = self.version.to_le_bytes().to_vec();
-        bytes.extend_from_slice(&CompactSize::new(self.inputs.len() as u64).to_bytes());
+        let mut bytes = Vec::with_capacity(self.serialized_len());
+        self.write_to(&mut bytes);
+        bytes
+    }
+
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        self.encode(out);
+    }
+
+    fn encode(&self, out: &mut impl ByteSink) {
+        out.put(&self.version.to_le_bytes());
+        CompactSize::new(self.inputs.len() as u64).encode(out);
         for input in &self.inputs {
-            bytes.extend_from_slice(&input.to_bytes());
+            input.encode(out);
         }
-        bytes.extend_from_slice(&CompactSize::new(self.outputs.len() as u64).to_bytes());
+        CompactSize::new(self.outputs.len() as u64).encode(out);
         for output in &self.outputs {
-            bytes.extend_from_slice(&output.to_bytes());
+            output.encode(out);
         }
-        bytes.extend_from_slice(&self.lock_time.to_le_bytes());
-        bytes
+        out.put(&self.lock_time.to_le_bytes());
+    }
+
+    pub fn serialize_into_slice(&self, buf: &mut [u8]) -> Result<usize, BitcoinError> {
+        let len = self.serialized_len();
+        if buf.len() < len {
+            return Err(BitcoinError::InsufficientBytes);
+        }
+        let mut sink = SliceSink {
+            buf: &mut buf[..len],
+            written: 0,
+        };
+        self.encode(&mut sink);
+        Ok(sink.written)
     }
 
     pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        Self::parse(bytes, None)
+    }
+
+    // Unlike from_bytes, trailing bytes after the transaction are an error.
+    pub fn from_hex(s: &str) -> Result<Self, BitcoinError> {
+        let bytes = rpc_hex(s.trim())?;
+        let (tx, consumed) = Self::from_bytes(&bytes)?;
+        if consumed != bytes.len() {
+            return Err(BitcoinError::InvalidFormat);
+        }
+        Ok(tx)
+    }
+
+    // Ranges cover the script bytes only, without their CompactSize length prefix.
+    // Input scriptSigs come first, followed by output scriptPubKeys.
+    pub fn from_bytes_with_script_offsets(
+        bytes: &[u8],
+    ) -> Result<(Self, Vec<Range<usize>>), BitcoinError> {
+        let mut trace = ParseTrace::default();
+        let (tx, _) = Self::parse(bytes, Some(&mut trace))?;
+        Ok((tx, trace.script_offsets))
+    }
+
+    // Unlike from_bytes, non-canonical CompactSize encodings are accepted and reported.
+    pub fn from_bytes_with_warnings(
+        bytes: &[u8],
+    ) -> Result<(Self, Vec<DecodeWarning>), BitcoinError> {
+        let mut trace = ParseTrace {
+            lenient: true,
+            ..ParseTrace::default()
+        };
+        let (tx, _) = Self::parse(bytes, Some(&mut trace))?;
+        Ok((tx, trace.warnings))
+    }
+
+    fn parse(
+        bytes: &[u8],
+        mut trace: Option<&mut ParseTrace>,
+    ) -> Result<(Self, usize), BitcoinError> {
         if bytes.len() < 4 {
             return Err(BitcoinError::InsufficientBytes);
         }
+        let lenient = trace.as_ref().is_some_and(|trace| trace.lenient);
         let version = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
-        let (input_count, compact_size_len) = CompactSize::from_bytes(&bytes[4..])?;
+        let (input_count, compact_size_len) =
+            parse_within(&bytes[4..], |b| CompactSize::decode(b, lenient))?;
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.compact_size(4, &input_count, compact_size_len);
+        }
         let mut cursor = 4 + compact_size_len;
         let mut inputs = Vec::new();
         for _ in 0..input_count.value {
-            let (input, input_len) = TransactionInput::from_bytes(&bytes[cursor..])?;
+            let (input, input_len) =
+                parse_within(&bytes[cursor..], |b| TransactionInput::decode(b, lenient))?;
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.script(cursor + 36, &input.script_sig, cursor + input_len - 4);
+            }
             inputs.push(input);
             cursor += input_len;
         }
-        let (output_count, compact_size_len) = CompactSize::from_bytes(&bytes[cursor..])?;
+        let (output_count, compact_size_len) =
+            parse_within(&bytes[cursor..], |b| CompactSize::decode(b, lenient))?;
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.compact_size(cursor, &output_count, compact_size_len);
+        }
         cursor += compact_size_len;
         let mut outputs = Vec::new();
         for _ in 0..output_count.value {
-            let (output, output_len) = TransactionOutput::from_bytes(&bytes[cursor..])?;
+            let (output, output_len) =
+                parse_within(&bytes[cursor..], |b| TransactionOutput::decode(b, lenient))?;
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.script(cursor + 8, &output.script_pubkey, cursor + output_len);
+            }
             outputs.push(output);
             cursor += output_len;
         }
@@ -303,26 +1119,1229 @@ impl BitcoinTransaction {
             cursor,
         ))
     }
-}
 
-impl fmt::Display for BitcoinTransaction {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "Transaction:")?;
-        writeln!(f, "  Version: {}", self.version)?;
-        writeln!(f, "  Inputs: [")?;
-        for input in &self.inputs {
-            writeln!(f, "    Input:")?;
-            writeln!(f, "      Previous Output:")?;
-            writeln!(
-                f,
-                "        Txid: {}",
-                hex::encode(input.previous_output.txid.0)
-            )?;
-            writeln!(
-                f,
-                "        Previous Output Vout: {}",
-                input.previous_output.vout
-            )?;
+    // Accepts the shape produced by bitcoind's decoderawtransaction. Txids are given
+    // in display order and values in BTC.
+    pub fn from_rpc_json(value: &serde_json::Value) -> Result<Self, BitcoinError> {
+        let version = rpc_u32(value, "version")?;
+        let lock_time = rpc_u32(value, "locktime")?;
+        let vin = value["vin"].as_array().ok_or(BitcoinError::InvalidFormat)?;
+        let vout = value["vout"]
+            .as_array()
+            .ok_or(BitcoinError::InvalidFormat)?;
+
+        let mut inputs = Vec::with_capacity(vin.len());
+        for input in vin {
+            let sequence = rpc_u32(input, "sequence")?;
+            if let Some(coinbase) = input["coinbase"].as_str() {
+                let script_sig = Script::new(rpc_hex(coinbase)?);
+                let previous_output = OutPoint::new([0u8; 32], 0xFFFFFFFF);
+                inputs.push(TransactionInput::new(previous_output, script_sig, sequence));
+                continue;
+            }
+            let txid_hex = input["txid"].as_str().ok_or(BitcoinError::InvalidFormat)?;
+            let mut txid =
+                Txid::from_slice(&rpc_hex(txid_hex)?).map_err(|_| BitcoinError::InvalidFormat)?;
+            txid.0.reverse();
+            let script_hex = input["scriptSig"]["hex"]
+                .as_str()
+                .ok_or(BitcoinError::InvalidFormat)?;
+            inputs.push(TransactionInput::new(
+                OutPoint::new(txid.0, rpc_u32(input, "vout")?),
+                Script::new(rpc_hex(script_hex)?),
+                sequence,
+            ));
+        }
+
+        let mut outputs = Vec::with_capacity(vout.len());
+        for output in vout {
+            let btc = output["value"]
+                .as_f64()
+                .ok_or(BitcoinError::InvalidFormat)?;
+            let sats = (btc * 100_000_000.0).round();
+            if !(0.0..=MAX_MONEY as f64).contains(&sats) {
+                return Err(BitcoinError::ValueOutOfRange);
+            }
+            let script_hex = output["scriptPubKey"]["hex"]
+                .as_str()
+                .ok_or(BitcoinError::InvalidFormat)?;
+            outputs.push(TransactionOutput::new(
+                sats as u64,
+                Script::new(rpc_hex(script_hex)?),
+            ));
+        }
+
+        Ok(BitcoinTransaction::new(version, inputs, outputs, lock_time))
+    }
+
+    pub fn txid(&self) -> Txid {
+        Txid(hash256(&self.to_bytes()))
+    }
+
+    pub fn txid_prefix8(&self) -> u64 {
+        self.txid().prefix8()
+    }
+
+    // Cheap corruption check for cached bytes. Not an identifier; use txid for that.
+    #[cfg(feature = "crc")]
+    pub fn crc32(&self) -> u32 {
+        crc32fast::hash(&self.to_bytes())
+    }
+
+    pub fn spent_outpoints(&self) -> impl Iterator<Item = &OutPoint> {
+        self.inputs.iter().map(|input| &input.previous_output)
+    }
+
+    pub fn has_duplicate_inputs(&self) -> bool {
+        let mut seen = HashSet::new();
+        !self.spent_outpoints().all(|outpoint| seen.insert(outpoint))
+    }
+
+    // CVE-2018-17144: nodes that skipped the duplicate-input check could be made to
+    // accept a transaction spending one outpoint twice, inflating the supply.
+    pub fn has_duplicate_input_inflation_risk(&self) -> bool {
+        self.has_duplicate_inputs()
+    }
+
+    pub fn spends_unconfirmed(&self, mempool_txids: &HashSet<Txid>) -> bool {
+        self.spent_outpoints()
+            .any(|outpoint| mempool_txids.contains(&outpoint.txid))
+    }
+
+    pub fn is_cpfp_child_of(&self, parent: &Self) -> bool {
+        let parent_txid = parent.txid();
+        self.spent_outpoints().any(|outpoint| {
+            outpoint.txid == parent_txid && (outpoint.vout as usize) < parent.outputs.len()
+        })
+    }
+
+    // Consensus treats the version as a signed 32-bit integer; relay only accepts 1 and 2.
+    pub fn is_version_standard(&self) -> bool {
+        matches!(self.version as i32, 1..=2)
+    }
+
+    pub fn lock_time_kind(&self) -> LockTime {
+        if self.lock_time < LOCKTIME_THRESHOLD {
+            LockTime::Height(self.lock_time)
+        } else {
+            LockTime::Time(self.lock_time)
+        }
+    }
+
+    // A lock time is ignored when it is zero or every input has a final sequence.
+    pub fn is_lock_time_enabled(&self) -> bool {
+        self.lock_time != 0 && self.inputs.iter().any(|input| input.sequence != 0xFFFFFFFF)
+    }
+
+    // `height` is the height of the block that would include the transaction and
+    // `time` the median time past of the chain tip (see median_time_past), as in
+    // Core's IsFinalTx.
+    pub fn is_final_at(&self, height: u32, time: u32) -> bool {
+        if !self.is_lock_time_enabled() {
+            return true;
+        }
+        match self.lock_time_kind() {
+            LockTime::Height(lock_height) => lock_height < height,
+            LockTime::Time(lock_time) => lock_time < time,
+        }
+    }
+
+    pub fn is_truc(&self) -> bool {
+        self.version == 3
+    }
+
+    // `parent_count` is the number of unconfirmed parents; BIP431 allows at most one,
+    // and a transaction with one must itself stay under the child size limit.
+    pub fn violates_truc_limits(&self, parent_count: usize) -> bool {
+        if !self.is_truc() {
+            return false;
+        }
+        let vsize = self.vsize();
+        vsize > TRUC_MAX_VSIZE
+            || parent_count > 1
+            || (parent_count == 1 && vsize > TRUC_CHILD_MAX_VSIZE)
+    }
+
+    pub fn signals_rbf(&self) -> bool {
+        self.inputs.iter().any(|input| input.sequence < 0xFFFFFFFE)
+    }
+
+    // BIP69 compares previous txids in their displayed (reversed) byte order.
+    pub fn is_bip69_sorted(&self) -> bool {
+        let input_key = |input: &TransactionInput| {
+            let mut txid = input.previous_output.txid.0;
+            txid.reverse();
+            (txid, input.previous_output.vout)
+        };
+        let output_key =
+            |output: &TransactionOutput| (output.value, output.script_pubkey.bytes.clone());
+        self.inputs
+            .windows(2)
+            .all(|pair| input_key(&pair[0]) <= input_key(&pair[1]))
+            && self
+                .outputs
+                .windows(2)
+                .all(|pair| output_key(&pair[0]) <= output_key(&pair[1]))
+    }
+
+    pub fn equivalent_ignoring_order(&self, other: &Self) -> bool {
+        fn sorted<T>(items: &[T], to_bytes: impl Fn(&T) -> Vec<u8>) -> Vec<Vec<u8>> {
+            let mut encoded: Vec<Vec<u8>> = items.iter().map(to_bytes).collect();
+            encoded.sort_unstable();
+            encoded
+        }
+        self.version == other.version
+            && self.lock_time == other.lock_time
+            && sorted(&self.inputs, TransactionInput::to_bytes)
+                == sorted(&other.inputs, TransactionInput::to_bytes)
+            && sorted(&self.outputs, TransactionOutput::to_bytes)
+                == sorted(&other.outputs, TransactionOutput::to_bytes)
+    }
+
+    // Heuristic only: payjoins merge inputs from sender and receiver, which tends to
+    // show up as several inputs of differing types feeding a payment plus change.
+    // Wallets mixing their own script types will also match.
+    pub fn looks_like_payjoin(&self) -> bool {
+        if self.inputs.len() < 2 || self.outputs.len() != 2 {
+            return false;
+        }
+        let input_types: HashSet<Option<ScriptType>> = self
+            .inputs
+            .iter()
+            .map(TransactionInput::inferred_spend_type)
+            .collect();
+        input_types.len() > 1
+            && self.outputs.iter().all(|output| {
+                output.value > 0 && output.script_pubkey.script_type() != ScriptType::OpReturn
+            })
+    }
+
+    pub fn structure_fingerprint(&self) -> StructureFingerprint {
+        let mut output_types: Vec<ScriptType> = self
+            .outputs
+            .iter()
+            .map(|output| output.script_pubkey.script_type())
+            .collect();
+        output_types.sort_unstable();
+        StructureFingerprint {
+            input_count: self.inputs.len(),
+            output_count: self.outputs.len(),
+            output_types,
+            uses_lock_time: self.lock_time != 0,
+            signals_rbf: self.signals_rbf(),
+            bip69_sorted: self.is_bip69_sorted(),
+        }
+    }
+
+    pub fn skeleton(&self) -> BitcoinTransaction {
+        let mut skeleton = self.clone();
+        for input in &mut skeleton.inputs {
+            input.script_sig = Script::new(Vec::new());
+        }
+        skeleton
+    }
+
+    // The transaction as legacy (pre-segwit) signature hashing sees it. Every scriptSig
+    // is left empty; the caller puts the script code into `input_index` before hashing.
+    // SIGHASH_SINGLE without a matching output is an error rather than Core's "one" hash.
+    pub fn sighash_skeleton(
+        &self,
+        input_index: usize,
+        sighash_type: u32,
+    ) -> Result<BitcoinTransaction, BitcoinError> {
+        if input_index >= self.inputs.len() {
+            return Err(BitcoinError::IndexOutOfBounds);
+        }
+        let mut skeleton = self.skeleton();
+        let base_type = sighash_type & 0x1F;
+        if base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE {
+            if base_type == SIGHASH_NONE {
+                skeleton.outputs.clear();
+            } else {
+                if input_index >= skeleton.outputs.len() {
+                    return Err(BitcoinError::IndexOutOfBounds);
+                }
+                skeleton.outputs.truncate(input_index + 1);
+                for output in &mut skeleton.outputs[..input_index] {
+                    *output = TransactionOutput::new(u64::MAX, Script::new(Vec::new()));
+                }
+            }
+            for (i, input) in skeleton.inputs.iter_mut().enumerate() {
+                if i != input_index {
+                    input.sequence = 0;
+                }
+            }
+        }
+        if sighash_type & SIGHASH_ANYONECANPAY != 0 {
+            skeleton.inputs = vec![skeleton.inputs.swap_remove(input_index)];
+        }
+        Ok(skeleton)
+    }
+
+    pub fn clear_input_signature(&mut self, index: usize) -> Result<(), BitcoinError> {
+        let input = self
+            .inputs
+            .get_mut(index)
+            .ok_or(BitcoinError::IndexOutOfBounds)?;
+        input.script_sig = Script::new(Vec::new());
+        Ok(())
+    }
+
+    // Spent txids plus every 32-byte push in the input and output scripts.
+    pub fn referenced_hashes(&self) -> Vec<[u8; 32]> {
+        let mut hashes: Vec<[u8; 32]> = self.spent_outpoints().map(|op| op.txid.0).collect();
+        let scripts = self
+            .inputs
+            .iter()
+            .map(|input| &input.script_sig)
+            .chain(self.outputs.iter().map(|output| &output.script_pubkey));
+        for script in scripts {
+            for data in script.pushed_data() {
+                if let Ok(hash) = <[u8; 32]>::try_from(data) {
+                    hashes.push(hash);
+                }
+            }
+        }
+        hashes
+    }
+
+    pub fn set_output_value(&mut self, index: usize, value: u64) -> Result<(), BitcoinError> {
+        if value > MAX_MONEY {
+            return Err(BitcoinError::ValueOutOfRange);
+        }
+        let output = self
+            .outputs
+            .get_mut(index)
+            .ok_or(BitcoinError::IndexOutOfBounds)?;
+        output.value = value;
+        Ok(())
+    }
+
+    // More than half of the inputs spend outputs that are dust at `fee_rate`, and
+    // they are merged into fewer outputs. `prevouts` are in input order.
+    pub fn is_dust_consolidation(&self, prevouts: &[TransactionOutput], fee_rate: f64) -> bool {
+        if prevouts.len() != self.inputs.len() || self.outputs.len() >= self.inputs.len() {
+            return false;
+        }
+        let dust_inputs = prevouts
+            .iter()
+            .filter(|prevout| prevout.is_dust_at(fee_rate))
+            .count();
+        dust_inputs * 2 > prevouts.len()
+    }
+
+    pub fn is_sweep(&self) -> bool {
+        !self.inputs.is_empty() && self.outputs.len() == 1
+    }
+
+    pub fn has_output(&self, script: &Script, min_value: u64) -> bool {
+        self.outputs
+            .iter()
+            .any(|output| output.script_pubkey == *script && output.value >= min_value)
+    }
+
+    pub fn owned_output_value(&self, owned_scripts: &HashSet<Script>) -> u64 {
+        self.outputs
+            .iter()
+            .filter(|output| owned_scripts.contains(&output.script_pubkey))
+            .map(|output| output.value)
+            .fold(0, u64::saturating_add)
+    }
+
+    pub fn spent_owned_value(&self, owned_utxos: &HashMap<OutPoint, u64>) -> u64 {
+        self.spent_outpoints()
+            .filter_map(|outpoint| owned_utxos.get(outpoint).copied())
+            .fold(0, u64::saturating_add)
+    }
+
+    pub fn burned_value(&self) -> u64 {
+        self.outputs
+            .iter()
+            .filter(|output| output.script_pubkey.is_provably_unspendable())
+            .map(|output| output.value)
+            .fold(0, u64::saturating_add)
+    }
+
+    // ValueOutOfRange if any output or the total exceeds MAX_MONEY, as in Core's
+    // CheckTransaction; parsed transactions may carry any u64 value. The reporting
+    // sums above (owned, spent-owned and burned value) saturate at u64::MAX instead.
+    pub fn total_output_value(&self) -> Result<u64, BitcoinError> {
+        self.outputs.iter().try_fold(0u64, |total, output| {
+            if output.value > MAX_MONEY {
+                return Err(BitcoinError::ValueOutOfRange);
+            }
+            total
+                .checked_add(output.value)
+                .filter(|&total| total <= MAX_MONEY)
+                .ok_or(BitcoinError::ValueOutOfRange)
+        })
+    }
+
+    pub fn fee(&self, prevouts: &HashMap<OutPoint, u64>) -> Result<u64, BitcoinError> {
+        self.check_value_conservation(|outpoint| prevouts.get(outpoint).copied())
+    }
+
+    // Returns the fee, resolving each spent outpoint's value on demand. Input values
+    // and their total are held to MAX_MONEY just like the outputs.
+    pub fn check_value_conservation<F>(&self, resolve: F) -> Result<u64, BitcoinError>
+    where
+        F: Fn(&OutPoint) -> Option<u64>,
+    {
+        let mut input_value = 0u64;
+        for outpoint in self.spent_outpoints() {
+            let value = resolve(outpoint).ok_or(BitcoinError::MissingPrevout)?;
+            input_value = input_value
+                .checked_add(value)
+                .filter(|&total| value <= MAX_MONEY && total <= MAX_MONEY)
+                .ok_or(BitcoinError::ValueOutOfRange)?;
+        }
+        input_value
+            .checked_sub(self.total_output_value()?)
+            .ok_or(BitcoinError::InsufficientFunds)
+    }
+
+    // `prevouts[i]` is the output spent by `inputs[i]`.
+    pub fn fee_from_prevouts(&self, prevouts: &[TransactionOutput]) -> Result<u64, BitcoinError> {
+        if prevouts.len() != self.inputs.len() {
+            return Err(BitcoinError::MissingPrevout);
+        }
+        let values: HashMap<OutPoint, u64> = self
+            .spent_outpoints()
+            .cloned()
+            .zip(prevouts.iter().map(|prevout| prevout.value))
+            .collect();
+        self.fee(&values)
+    }
+
+    // Both transactions are assumed to spend the same inputs in the same order,
+    // as an RBF replacement normally does.
+    pub fn fee_delta(
+        &self,
+        other: &Self,
+        prevouts: &[TransactionOutput],
+    ) -> Result<i64, BitcoinError> {
+        let fee = self.fee_from_prevouts(prevouts)? as i64;
+        let other_fee = other.fee_from_prevouts(prevouts)? as i64;
+        Ok(fee - other_fee)
+    }
+
+    // BIP141 sigop cost. `prevouts` are in input order; inputs without one only count
+    // their scriptSig. P2WSH witness scripts are not modelled and count zero.
+    pub fn sigop_cost(&self, prevouts: &[TransactionOutput]) -> usize {
+        let legacy: usize = self
+            .inputs
+            .iter()
+            .map(|input| input.script_sig.sigop_count(false))
+            .chain(
+                self.outputs
+                    .iter()
+                    .map(|output| output.script_pubkey.sigop_count(false)),
+            )
+            .sum();
+        let mut cost = legacy * WITNESS_SCALE_FACTOR;
+        for (input, prevout) in self.inputs.iter().zip(prevouts) {
+            let mut witness_program = prevout.script_pubkey.clone();
+            if prevout.script_pubkey.script_type() == ScriptType::P2sh {
+                let Some(redeem_script) = input.script_sig.p2sh_redeem_script() else {
+                    continue;
+                };
+                cost += redeem_script.sigop_count(true) * WITNESS_SCALE_FACTOR;
+                witness_program = redeem_script;
+            }
+            if matches!(witness_program.witness_program(), Some((0, program)) if program.len() == 20)
+            {
+                cost += 1;
+            }
+        }
+        cost
+    }
+
+    // Core bills sigop-heavy transactions as if each sigop cost DEFAULT_BYTES_PER_SIGOP
+    // vbytes; sigop cost is already scaled by WITNESS_SCALE_FACTOR, so this is in weight units.
+    pub fn relay_weight(&self, prevouts: &[TransactionOutput]) -> usize {
+        self.weight()
+            .max(self.sigop_cost(prevouts) * DEFAULT_BYTES_PER_SIGOP)
+    }
+
+    // Witness data is not modelled yet, so every byte carries the full weight of 4.
+    pub fn weight(&self) -> usize {
+        self.serialized_len() * 4
+    }
+
+    pub fn vsize(&self) -> usize {
+        self.weight().div_ceil(4)
+    }
+
+    pub fn fee_rate(&self, prevouts: &HashMap<OutPoint, u64>) -> Result<f64, BitcoinError> {
+        Ok(self.fee(prevouts)? as f64 / self.vsize() as f64)
+    }
+
+    // `fee_table` holds (minimum sat/vB, target blocks) pairs in any order. Returns the
+    // fastest target this transaction's rate meets, or u32::MAX if it meets none or
+    // its fee cannot be computed from `prevouts`.
+    pub fn estimate_confirmation_blocks(
+        &self,
+        prevouts: &[TransactionOutput],
+        fee_table: &[(f64, u32)],
+    ) -> u32 {
+        let Ok(fee) = self.fee_from_prevouts(prevouts) else {
+            return u32::MAX;
+        };
+        let rate = fee as f64 / self.vsize() as f64;
+        fee_table
+            .iter()
+            .filter(|(min_rate, _)| rate >= *min_rate)
+            .map(|(_, blocks)| *blocks)
+            .min()
+            .unwrap_or(u32::MAX)
+    }
+
+    // BIP125 rule 4: the replacement must also pay for its own relay bandwidth.
+    pub fn min_rbf_replacement_fee(
+        &self,
+        replaced_fees: u64,
+        incremental_rate: f64,
+    ) -> Result<u64, BitcoinError> {
+        replaced_fees
+            .checked_add(fee_at_rate(self.vsize(), incremental_rate)?)
+            .ok_or(BitcoinError::ValueOutOfRange)
+    }
+
+    // `prevouts` pairs each input's spent output with the height it confirmed at,
+    // in input order; None when the lengths differ. Coins confirmed after
+    // `current_height` count as zero days.
+    pub fn coin_days_destroyed(
+        &self,
+        prevouts: &[(TransactionOutput, u32)],
+        current_height: u32,
+    ) -> Option<f64> {
+        if prevouts.len() != self.inputs.len() {
+            return None;
+        }
+        let cdd = prevouts
+            .iter()
+            .map(|(prevout, input_height)| {
+                let days = current_height.saturating_sub(*input_height) as f64 / 144.0;
+                prevout.value as f64 / 100_000_000.0 * days
+            })
+            .sum();
+        Some(cdd)
+    }
+
+    // (min, max, mean) age in blocks of the coins spent, with `input_heights` in input
+    // order. None when the lengths differ or there are no inputs.
+    pub fn input_age_spread(
+        &self,
+        input_heights: &[u32],
+        current_height: u32,
+    ) -> Option<(u32, u32, f64)> {
+        if input_heights.len() != self.inputs.len() || input_heights.is_empty() {
+            return None;
+        }
+        let ages: Vec<u32> = input_heights
+            .iter()
+            .map(|&height| current_height.saturating_sub(height))
+            .collect();
+        let mean = ages.iter().map(|&age| age as f64).sum::<f64>() / ages.len() as f64;
+        Some((*ages.iter().min()?, *ages.iter().max()?, mean))
+    }
+}
+
+// Negative, NaN and infinite rates would otherwise cast to a zero or saturated fee.
+fn validate_fee_rate(fee_rate: f64) -> Result<(), BitcoinError> {
+    if !fee_rate.is_finite() || fee_rate < 0.0 {
+        return Err(BitcoinError::ValueOutOfRange);
+    }
+    Ok(())
+}
+
+// Fee in sats for `vsize` at `fee_rate` sat/vB, rounded up. ValueOutOfRange for an
+// invalid rate or a fee that does not fit in a u64.
+fn fee_at_rate(vsize: usize, fee_rate: f64) -> Result<u64, BitcoinError> {
+    validate_fee_rate(fee_rate)?;
+    let fee = (vsize as f64 * fee_rate).ceil();
+    if fee >= u64::MAX as f64 {
+        return Err(BitcoinError::ValueOutOfRange);
+    }
+    Ok(fee as u64)
+}
+
+pub fn package_fee_rate(
+    txs: &[BitcoinTransaction],
+    prevouts: &HashMap<OutPoint, u64>,
+) -> Result<f64, BitcoinError> {
+    let mut total_fee = 0u64;
+    let mut total_vsize = 0usize;
+    for tx in txs {
+        total_fee = total_fee
+            .checked_add(tx.fee(prevouts)?)
+            .ok_or(BitcoinError::ValueOutOfRange)?;
+        total_vsize += tx.vsize();
+    }
+    if total_vsize == 0 {
+        return Ok(0.0);
+    }
+    Ok(total_fee as f64 / total_vsize as f64)
+}
+
+// 10th, 25th, 50th, 75th and 90th percentiles by nearest rank: the p-th percentile
+// is the ceil(p/100 * n)-th smallest rate. All zero when empty.
+pub fn fee_rate_percentiles(rates: &[f64]) -> [f64; 5] {
+    if rates.is_empty() {
+        return [0.0; 5];
+    }
+    let mut sorted = rates.to_vec();
+    sorted.sort_unstable_by(f64::total_cmp);
+    [10, 25, 50, 75, 90].map(|p| {
+        let rank = (p * sorted.len()).div_ceil(100).max(1);
+        sorted[rank - 1]
+    })
+}
+
+// Fee saved by spending `num_inputs` now rather than at `future_fee_rate`.
+// Negative when fees are expected to fall; zero for unknown input types.
+pub fn consolidation_savings(
+    num_inputs: usize,
+    input_type: ScriptType,
+    future_fee_rate: f64,
+    current_fee_rate: f64,
+) -> i64 {
+    let Some(input_vsize) = input_type.input_vsize() else {
+        return 0;
+    };
+    let vsize = (num_inputs * input_vsize) as f64;
+    (vsize * (future_fee_rate - current_fee_rate)).round() as i64
+}
+
+// An empty-scriptSig input spending `prevout`, plus the vsize its signing data will add.
+// The unsigned input is already counted by vsize(); only the signing data is extra.
+fn unsigned_input(
+    outpoint: &OutPoint,
+    prevout: &TransactionOutput,
+) -> Result<(TransactionInput, usize), BitcoinError> {
+    let input_vsize = prevout
+        .script_pubkey
+        .script_type()
+        .input_vsize()
+        .ok_or(BitcoinError::InvalidFormat)?;
+    let input = TransactionInput::new(outpoint.clone(), Script::new(vec![]), 0xFFFFFFFF);
+    let signature_vsize = input_vsize - input.serialized_len();
+    Ok((input, signature_vsize))
+}
+
+// Spends `utxos` in the given order until they cover `amount` plus the fee for the
+// estimated vsize. Change below the dust threshold is left to the fee instead.
+// A dust or above-MAX_MONEY `amount`, an invalid `fee_rate`, or UTXOs totalling
+// more than MAX_MONEY give ValueOutOfRange.
+pub fn build_spend(
+    utxos: &[(OutPoint, TransactionOutput)],
+    destination: &Script,
+    amount: u64,
+    change: &Script,
+    fee_rate: f64,
+) -> Result<BitcoinTransaction, BitcoinError> {
+    let mut tx = BitcoinTransaction::new(
+        2,
+        vec![],
+        vec![TransactionOutput::new(amount, destination.clone())],
+        0,
+    );
+    validate_fee_rate(fee_rate)?;
+    if amount > MAX_MONEY || tx.outputs[0].is_dust() {
+        return Err(BitcoinError::ValueOutOfRange);
+    }
+    let mut total_in = 0u64;
+    let mut signature_vsize = 0usize;
+    for (outpoint, prevout) in utxos {
+        let (input, input_signature_vsize) = unsigned_input(outpoint, prevout)?;
+        signature_vsize += input_signature_vsize;
+        tx.inputs.push(input);
+        total_in = total_in
+            .checked_add(prevout.value)
+            .filter(|&total| total <= MAX_MONEY)
+            .ok_or(BitcoinError::ValueOutOfRange)?;
+
+        // None when the fee is so large that it, or amount plus fee, overflows.
+        let needed_for = |tx: &BitcoinTransaction| {
+            fee_at_rate(tx.vsize() + signature_vsize, fee_rate)
+                .ok()
+                .and_then(|fee| amount.checked_add(fee))
+        };
+        let mut with_change = tx.clone();
+        with_change
+            .outputs
+            .push(TransactionOutput::new(0, change.clone()));
+        if let Some(change_value) =
+            needed_for(&with_change).and_then(|needed| total_in.checked_sub(needed))
+        {
+            with_change.outputs[1].value = change_value;
+            if !with_change.outputs[1].is_dust() {
+                return Ok(with_change);
+            }
+        }
+        if needed_for(&tx).is_some_and(|needed| total_in >= needed) {
+            return Ok(tx);
+        }
+    }
+    Err(BitcoinError::InsufficientFunds)
+}
+
+// Spends every UTXO into one `destination` output worth the total minus the fee for
+// the estimated vsize. Fails with InsufficientFunds if what is left would be dust,
+// and with ValueOutOfRange for an invalid `fee_rate` or UTXOs totalling more than
+// MAX_MONEY.
+pub fn build_sweep(
+    utxos: &[(OutPoint, TransactionOutput)],
+    destination: &Script,
+    fee_rate: f64,
+) -> Result<BitcoinTransaction, BitcoinError> {
+    let mut tx = BitcoinTransaction::new(
+        2,
+        vec![],
+        vec![TransactionOutput::new(0, destination.clone())],
+        0,
+    );
+    validate_fee_rate(fee_rate)?;
+    let mut total_in = 0u64;
+    let mut signature_vsize = 0usize;
+    for (outpoint, prevout) in utxos {
+        let (input, input_signature_vsize) = unsigned_input(outpoint, prevout)?;
+        signature_vsize += input_signature_vsize;
+        tx.inputs.push(input);
+        total_in = total_in
+            .checked_add(prevout.value)
+            .filter(|&total| total <= MAX_MONEY)
+            .ok_or(BitcoinError::ValueOutOfRange)?;
+    }
+    let fee = fee_at_rate(tx.vsize() + signature_vsize, fee_rate)
+        .map_err(|_| BitcoinError::InsufficientFunds)?;
+    tx.outputs[0].value = total_in
+        .checked_sub(fee)
+        .ok_or(BitcoinError::InsufficientFunds)?;
+    if utxos.is_empty() || tx.outputs[0].is_dust() {
+        return Err(BitcoinError::InsufficientFunds);
+    }
+    Ok(tx)
+}
+
+// Shortest prefix of `bytes` that fails BitcoinTransaction::from_bytes with the same
+// error as the full input. Input that parses successfully is returned unchanged.
+// Truncation errors (InsufficientBytes) always minimize to an empty input, since
+// the empty prefix already fails that way.
+pub fn minimize_failing_input(bytes: &[u8]) -> Vec<u8> {
+    let Err(expected) = BitcoinTransaction::from_bytes(bytes) else {
+        return bytes.to_vec();
+    };
+    (0..bytes.len())
+        .map(|len| &bytes[..len])
+        .find(|prefix| BitcoinTransaction::from_bytes(prefix).err().as_ref() == Some(&expected))
+        .unwrap_or(bytes)
+        .to_vec()
+}
+
+fn rpc_u32(value: &serde_json::Value, key: &str) -> Result<u32, BitcoinError> {
+    value[key]
+        .as_u64()
+        .and_then(|n| u32::try_from(n).ok())
+        .ok_or(BitcoinError::InvalidFormat)
+}
+
+fn rpc_hex(s: &str) -> Result<Vec<u8>, BitcoinError> {
+    hex::decode(s).map_err(|_| BitcoinError::InvalidFormat)
+}
+
+pub fn output_vsize_for_script(script: &Script) -> usize {
+    8 + script.serialized_len()
+}
+
+pub fn multisig_redeem_script(m: u8, pubkeys: &[Vec<u8>]) -> Result<Script, BitcoinError> {
+    let n = pubkeys.len();
+    if m == 0 || m as usize > n || n > 16 {
+        return Err(BitcoinError::InvalidFormat);
+    }
+    if pubkeys.iter().any(|key| key.len() != 33 && key.len() != 65) {
+        return Err(BitcoinError::InvalidFormat);
+    }
+    let mut script = Script::new(Vec::new());
+    script.push_int(m as i64);
+    for key in pubkeys {
+        script.push_data(key);
+    }
+    script.push_int(n as i64);
+    script.push_opcode(0xAE);
+    Ok(script)
+}
+
+// Keys are the lower vsize bound of each bucket in SIZE_BUCKETS; every bucket is present.
+// Buckets are half-open [lower, next lower), so exactly 1000 vB counts as "1000 and up".
+pub fn tx_size_histogram(txs: &[BitcoinTransaction]) -> BTreeMap<usize, usize> {
+    let mut histogram: BTreeMap<usize, usize> =
+        SIZE_BUCKETS.iter().map(|&bucket| (bucket, 0)).collect();
+    for tx in txs {
+        let vsize = tx.vsize();
+        let bucket = SIZE_BUCKETS
+            .iter()
+            .rev()
+            .find(|&&lower| vsize >= lower)
+            .unwrap();
+        *histogram.get_mut(bucket).unwrap() += 1;
+    }
+    histogram
+}
+
+pub fn encode_all(txs: &[BitcoinTransaction]) -> Vec<u8> {
+    let total_len = txs.iter().map(BitcoinTransaction::serialized_len).sum();
+    let mut bytes = Vec::with_capacity(total_len);
+    for tx in txs {
+        tx.write_to(&mut bytes);
+    }
+    bytes
+}
+
+// One result per non-blank line; lines starting with '#' are skipped.
+pub fn decode_hex_lines(input: &str) -> Vec<Result<BitcoinTransaction, BitcoinError>> {
+    input
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(BitcoinTransaction::from_hex)
+        .collect()
+}
+
+// Returns (parent_index, child_index) pairs, one per parent/child relationship.
+pub fn dependency_edges(txs: &[BitcoinTransaction]) -> Vec<(usize, usize)> {
+    let index_by_txid: HashMap<Txid, usize> = txs
+        .iter()
+        .enumerate()
+        .map(|(i, tx)| (tx.txid(), i))
+        .collect();
+    let mut edges = Vec::new();
+    for (child, tx) in txs.iter().enumerate() {
+        let mut parents: Vec<usize> = tx
+            .spent_outpoints()
+            .filter_map(|outpoint| index_by_txid.get(&outpoint.txid).copied())
+            .collect();
+        parents.sort_unstable();
+        parents.dedup();
+        edges.extend(parents.into_iter().map(|parent| (parent, child)));
+    }
+    edges
+}
+
+fn reachable(edges: &[(usize, usize)], start: usize, upward: bool) -> HashSet<usize> {
+    let mut seen = HashSet::new();
+    let mut stack = vec![start];
+    while let Some(current) = stack.pop() {
+        for &(parent, child) in edges {
+            let (from, to) = if upward {
+                (child, parent)
+            } else {
+                (parent, child)
+            };
+            if from == current && seen.insert(to) {
+                stack.push(to);
+            }
+        }
+    }
+    seen
+}
+
+// In-slice ancestors of `txs[index]`, not including the transaction itself.
+pub fn ancestors(txs: &[BitcoinTransaction], index: usize) -> HashSet<usize> {
+    reachable(&dependency_edges(txs), index, true)
+}
+
+// In-slice descendants of `txs[index]`, not including the transaction itself.
+pub fn descendants(txs: &[BitcoinTransaction], index: usize) -> HashSet<usize> {
+    reachable(&dependency_edges(txs), index, false)
+}
+
+// `prevouts` must also cover outputs of in-slice ancestors that the set spends.
+pub fn ancestor_fee_rate(
+    txs: &[BitcoinTransaction],
+    index: usize,
+    prevouts: &HashMap<OutPoint, u64>,
+) -> Result<f64, BitcoinError> {
+    if index >= txs.len() {
+        return Err(BitcoinError::IndexOutOfBounds);
+    }
+    let mut package: Vec<BitcoinTransaction> = ancestors(txs, index)
+        .into_iter()
+        .map(|i| txs[i].clone())
+        .collect();
+    package.push(txs[index].clone());
+    package_fee_rate(&package, prevouts)
+}
+
+// Each transaction's ancestor set counts the transaction itself, as Core does.
+// Use DEFAULT_ANCESTOR_LIMIT and DEFAULT_ANCESTOR_SIZE_LIMIT_VSIZE for relay policy.
+pub fn validate_package_limits(
+    txs: &[BitcoinTransaction],
+    max_count: usize,
+    max_vsize: usize,
+) -> Result<(), BitcoinError> {
+    let edges = dependency_edges(txs);
+    for (index, tx) in txs.iter().enumerate() {
+        let ancestors = reachable(&edges, index, true);
+        let vsize = tx.vsize() + ancestors.iter().map(|&i| txs[i].vsize()).sum::<usize>();
+        if ancestors.len() + 1 > max_count || vsize > max_vsize {
+            return Err(BitcoinError::LimitExceeded);
+        }
+    }
+    Ok(())
+}
+
+// One node per transaction, labelled with its txid, and one edge per spent output
+// of another transaction in the slice. Edge labels come from `prevouts`, falling
+// back to the parent's output value.
+pub fn transactions_to_dot(
+    txs: &[BitcoinTransaction],
+    prevouts: &HashMap<OutPoint, u64>,
+) -> String {
+    let txids: Vec<Txid> = txs.iter().map(BitcoinTransaction::txid).collect();
+    let index_by_txid: HashMap<&Txid, usize> = txids
+        .iter()
+        .enumerate()
+        .map(|(i, txid)| (txid, i))
+        .collect();
+    let mut dot = String::from("digraph transactions {\n");
+    for (i, txid) in txids.iter().enumerate() {
+        dot.push_str(&format!(
+            "    tx{} [label=\"{}\"];\n",
+            i,
+            txid.to_display_string()
+        ));
+    }
+    for (child, tx) in txs.iter().enumerate() {
+        for outpoint in tx.spent_outpoints() {
+            let Some(&parent) = index_by_txid.get(&outpoint.txid) else {
+                continue;
+            };
+            let value = prevouts.get(outpoint).copied().or_else(|| {
+                txs[parent]
+                    .outputs
+                    .get(outpoint.vout as usize)
+                    .map(|output| output.value)
+            });
+            match value {
+                Some(value) => dot.push_str(&format!(
+                    "    tx{} -> tx{} [label=\"{}\"];\n",
+                    parent, child, value
+                )),
+                None => dot.push_str(&format!("    tx{} -> tx{};\n", parent, child)),
+            }
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+// Orders by descending fee rate, but never places a child ahead of a parent
+// that appears in the same slice. Each entry is (transaction, fee, vsize).
+pub fn sort_for_block(txs: &mut [(BitcoinTransaction, u64, usize)]) {
+    let plain: Vec<BitcoinTransaction> = txs.iter().map(|(tx, _, _)| tx.clone()).collect();
+    let mut parents = vec![Vec::new(); txs.len()];
+    for (parent, child) in dependency_edges(&plain) {
+        parents[child].push(parent);
+    }
+    let rate = |i: usize| txs[i].1 as f64 / txs[i].2 as f64;
+
+    let mut placed = vec![false; txs.len()];
+    let mut order = Vec::with_capacity(txs.len());
+    while order.len() < txs.len() {
+        let next = (0..txs.len())
+            .filter(|&i| !placed[i] && parents[i].iter().all(|&p| placed[p]))
+            .fold(None, |best: Option<usize>, i| match best {
+                Some(b) if rate(b) >= rate(i) => Some(b),
+                _ => Some(i),
+            });
+        let Some(next) = next else {
+            // A dependency cycle cannot come from valid transactions; keep the rest as-is.
+            order.extend((0..txs.len()).filter(|&i| !placed[i]));
+            break;
+        };
+        placed[next] = true;
+        order.push(next);
+    }
+
+    let sorted: Vec<_> = order.iter().map(|&i| txs[i].clone()).collect();
+    txs.clone_from_slice(&sorted);
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub version: u32,
+    pub prev_block_hash: [u8; 32],
+    pub merkle_root: [u8; 32],
+    pub time: u32,
+    pub bits: u32,
+    pub nonce: u32,
+}
+
+impl BlockHeader {
+    pub const SIZE: usize = 80;
+
+    pub fn block_hash(&self) -> [u8; 32] {
+        hash256(&self.to_bytes())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::SIZE);
+        self.write_to(&mut bytes);
+        bytes
+    }
+
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        self.encode(out);
+    }
+
+    fn encode(&self, out: &mut impl ByteSink) {
+        out.put(&self.version.to_le_bytes());
+        out.put(&self.prev_block_hash);
+        out.put(&self.merkle_root);
+        out.put(&self.time.to_le_bytes());
+        out.put(&self.bits.to_le_bytes());
+        out.put(&self.nonce.to_le_bytes());
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        if bytes.len() < Self::SIZE {
+            return Err(BitcoinError::InsufficientBytes);
+        }
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let header = BlockHeader {
+            version: u32_at(0),
+            prev_block_hash: bytes[4..36].try_into().unwrap(),
+            merkle_root: bytes[36..68].try_into().unwrap(),
+            time: u32_at(68),
+            bits: u32_at(72),
+            nonce: u32_at(76),
+        };
+        Ok((header, Self::SIZE))
+    }
+}
+
+// Payload of the p2p `headers` message: a count, then each header followed by a
+// transaction count that is always zero.
+pub fn parse_headers_message(bytes: &[u8]) -> Result<Vec<BlockHeader>, BitcoinError> {
+    let (count, mut offset) = CompactSize::from_bytes(bytes)?;
+    let mut headers = Vec::new();
+    for _ in 0..count.value {
+        let (header, consumed) = BlockHeader::from_bytes(&bytes[offset..])?;
+        offset += consumed;
+        match bytes.get(offset) {
+            Some(0x00) => offset += 1,
+            Some(_) => return Err(BitcoinError::InvalidFormat),
+            None => return Err(BitcoinError::InsufficientBytes),
+        }
+        headers.push(header);
+    }
+    if offset != bytes.len() {
+        return Err(BitcoinError::InvalidFormat);
+    }
+    Ok(headers)
+}
+
+// `timestamps` are in chain order, oldest first; only the last 11 are used.
+pub fn median_time_past(timestamps: &[u32]) -> Option<u32> {
+    if timestamps.is_empty() {
+        return None;
+    }
+    let mut window = timestamps[timestamps.len().saturating_sub(11)..].to_vec();
+    window.sort_unstable();
+    Some(window[window.len() / 2])
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct BlockWeightAccumulator {
+    weight: usize,
+}
+
+impl BlockWeightAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A transaction that does not fit is rejected without changing the running total.
+    pub fn add(&mut self, tx: &BitcoinTransaction) -> Result<(), BitcoinError> {
+        let weight = tx.weight();
+        if weight > self.remaining_weight() {
+            return Err(BitcoinError::LimitExceeded);
+        }
+        self.weight += weight;
+        Ok(())
+    }
+
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    pub fn remaining_weight(&self) -> usize {
+        MAX_BLOCK_WEIGHT - self.weight
+    }
+}
+
+// BIP158 basic filter parameters.
+const FILTER_P: u8 = 19;
+const FILTER_M: u64 = 784931;
+
+fn filter_hash_to_range(block_hash: &[u8; 32], item: &[u8], range: u64) -> u64 {
+    let k0 = u64::from_le_bytes(block_hash[0..8].try_into().unwrap());
+    let k1 = u64::from_le_bytes(block_hash[8..16].try_into().unwrap());
+    let mut hasher = SipHasher24::new_with_keys(k0, k1);
+    hasher.write(item);
+    ((hasher.finish() as u128 * range as u128) >> 64) as u64
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    used_bits: u8,
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.used_bits == 0 {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> self.used_bits;
+        }
+        self.used_bits = (self.used_bits + 1) % 8;
+    }
+
+    fn write_golomb_rice(&mut self, value: u64, p: u8) {
+        for _ in 0..(value >> p) {
+            self.write_bit(true);
+        }
+        self.write_bit(false);
+        for i in (0..p).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.bytes.get(self.position / 8)?;
+        let bit = byte & (0x80 >> (self.position % 8)) != 0;
+        self.position += 1;
+        Some(bit)
+    }
+
+    fn read_golomb_rice(&mut self, p: u8) -> Option<u64> {
+        let mut quotient = 0u64;
+        while self.read_bit()? {
+            quotient += 1;
+        }
+        let mut value = quotient.checked_shl(p as u32)?;
+        for i in (0..p).rev() {
+            if self.read_bit()? {
+                value |= 1 << i;
+            }
+        }
+        Some(value)
+    }
+}
+
+// `block_hash` is in internal byte order, i.e. the reverse of the displayed hash.
+pub fn build_block_filter(scripts: &[Script], block_hash: &[u8; 32]) -> Vec<u8> {
+    let mut items: Vec<&[u8]> = scripts
+        .iter()
+        .filter(|script| !script.is_empty())
+        .map(|script| script.bytes.as_slice())
+        .collect();
+    items.sort_unstable();
+    items.dedup();
+
+    let n = items.len() as u64;
+    let mut filter = CompactSize::new(n).to_bytes();
+    let range = n * FILTER_M;
+    let mut values: Vec<u64> = items
+        .iter()
+        .map(|item| filter_hash_to_range(block_hash, item, range))
+        .collect();
+    values.sort_unstable();
+
+    let mut writer = BitWriter {
+        bytes: Vec::new(),
+        used_bits: 0,
+    };
+    let mut last = 0;
+    for value in values {
+        writer.write_golomb_rice(value - last, FILTER_P);
+        last = value;
+    }
+    filter.extend_from_slice(&writer.bytes);
+    filter
+}
+
+pub fn filter_matches(filter: &[u8], block_hash: &[u8; 32], queries: &[&[u8]]) -> bool {
+    let Ok((n, n_len)) = CompactSize::from_bytes(filter) else {
+        return false;
+    };
+    let Some(range) = n.value.checked_mul(FILTER_M) else {
+        return false;
+    };
+    let mut targets: Vec<u64> = queries
+        .iter()
+        .map(|query| filter_hash_to_range(block_hash, query, range))
+        .collect();
+    targets.sort_unstable();
+
+    let mut reader = BitReader {
+        bytes: &filter[n_len..],
+        position: 0,
+    };
+    let mut targets = targets.into_iter().peekable();
+    let mut value = 0u64;
+    for _ in 0..n.value {
+        let Some(next) = reader
+            .read_golomb_rice(FILTER_P)
+            .and_then(|delta| value.checked_add(delta))
+        else {
+            return false;
+        };
+        value = next;
+        while let Some(&target) = targets.peek() {
+            if target == value {
+                return true;
+            }
+            if target > value {
+                break;
+            }
+            targets.next();
+        }
+        if targets.peek().is_none() {
+            return false;
+        }
+    }
+    false
+}
+
+impl fmt::Display for BitcoinTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Transaction:")?;
+        writeln!(f, "  Version: {}", self.version)?;
+        writeln!(f, "  Inputs: [")?;
+        for input in &self.inputs {
+            writeln!(f, "    Input:")?;
+            writeln!(f, "      Previous Output:")?;
+            writeln!(
+                f,
+                "        Txid: {}",
+                hex::encode(input.previous_output.txid.0)
+            )?;
+            writeln!(
+                f,
+                "        Previous Output Vout: {}",
+                input.previous_output.vout
+            )?;
             writeln!(f, "      Script Sig:")?;
             writeln!(f, "        Length: {}", input.script_sig.bytes.len())?;
             writeln!(f, "        Bytes: {}", hex::encode(&input.script_sig.bytes))?;
@@ -345,3 +2364,24 @@ impl fmt::Display for BitcoinTransaction {
         writeln!(f, "  Lock Time: {}", self.lock_time)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "sub-parser consumed 3 of 2 bytes")]
+    fn test_parse_within_rejects_overlong_consumption() {
+        let _ = parse_within(&[0u8; 2], |_| Ok(((), 3)));
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn test_parse_within_rejects_overlong_consumption() {
+        assert_eq!(
+            parse_within(&[0u8; 2], |_| Ok(((), 3))),
+            Err(BitcoinError::InvalidFormat)
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use rust_week_3_exercises::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[cfg(test)]
 mod tests {
@@ -10,6 +11,19 @@ mod tests {
         txid
     }
 
+    fn p2wpkh_script(tag: u8) -> Script {
+        let mut bytes = vec![0x00, 0x14];
+        bytes.extend_from_slice(&[tag; 20]);
+        Script::new(bytes)
+    }
+
+    fn p2pkh_script(tag: u8) -> Script {
+        let mut bytes = vec![0x76, 0xA9, 0x14];
+        bytes.extend_from_slice(&[tag; 20]);
+        bytes.extend_from_slice(&[0x88, 0xAC]);
+        Script::new(bytes)
+    }
+
     #[test]
     fn test_compact_size_serialization() {
         let tests = vec![
@@ -123,4 +137,2225 @@ mod tests {
         assert!(output.contains("Lock Time: 0"));
         assert!(output.contains("Previous Output Vout: 7"));
     }
+
+    #[test]
+    fn test_txid_serde_json_and_bincode() {
+        let txid = Txid(dummy_txid(0xAB));
+
+        let json = serde_json::to_string(&txid).unwrap();
+        assert_eq!(json, format!("\"{}\"", hex::encode(txid.0)));
+        assert_eq!(serde_json::from_str::<Txid>(&json).unwrap(), txid);
+
+        let bin = bincode::serialize(&txid).unwrap();
+        assert!(bin.len() < json.len());
+        assert!(bin.ends_with(&txid.0));
+        assert_eq!(bincode::deserialize::<Txid>(&bin).unwrap(), txid);
+    }
+
+    #[test]
+    fn test_bitcoin_tx_bincode_roundtrip() {
+        let input = TransactionInput::new(
+            OutPoint::new(dummy_txid(0x01), 1),
+            Script::new(vec![0x51]),
+            0xFFFFFFFF,
+        );
+        let output = TransactionOutput::new(5000, Script::new(vec![0x6A]));
+        let tx = BitcoinTransaction::new(2, vec![input], vec![output], 0);
+
+        let bin = bincode::serialize(&tx).unwrap();
+        let parsed: BitcoinTransaction = bincode::deserialize(&bin).unwrap();
+        assert_eq!(parsed, tx);
+    }
+
+    #[test]
+    fn test_spends_unconfirmed() {
+        let parent = Txid(dummy_txid(0x10));
+        let confirmed = Txid(dummy_txid(0x20));
+        let tx = BitcoinTransaction::new(
+            2,
+            vec![
+                TransactionInput::new(OutPoint::new(confirmed.0, 0), Script::new(vec![]), 0),
+                TransactionInput::new(OutPoint::new(parent.0, 1), Script::new(vec![]), 0),
+            ],
+            vec![TransactionOutput::new(1000, Script::new(vec![0x51]))],
+            0,
+        );
+
+        let mut mempool = HashSet::new();
+        assert!(!tx.spends_unconfirmed(&mempool));
+        mempool.insert(parent);
+        assert!(tx.spends_unconfirmed(&mempool));
+    }
+
+    #[test]
+    fn test_package_fee_rate() {
+        let funding = OutPoint::new(dummy_txid(0x01), 0);
+        let parent = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                funding.clone(),
+                Script::new(vec![]),
+                0,
+            )],
+            vec![TransactionOutput::new(99_900, Script::new(vec![0x51]))],
+            0,
+        );
+        let child_input = OutPoint::new(dummy_txid(0x02), 0);
+        let child = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                child_input.clone(),
+                Script::new(vec![]),
+                0,
+            )],
+            vec![TransactionOutput::new(90_000, Script::new(vec![0x51]))],
+            0,
+        );
+
+        let mut prevouts = HashMap::new();
+        prevouts.insert(funding, 100_000);
+        prevouts.insert(child_input, 99_900);
+
+        let parent_rate = 100.0 / parent.vsize() as f64;
+        let child_rate = 9_900.0 / child.vsize() as f64;
+        let package_rate = package_fee_rate(&[parent.clone(), child], &prevouts).unwrap();
+        assert!(package_rate > parent_rate);
+        assert!(package_rate < child_rate);
+
+        assert_eq!(
+            package_fee_rate(&[parent], &HashMap::new()),
+            Err(BitcoinError::MissingPrevout)
+        );
+    }
+
+    #[test]
+    fn test_total_output_value_rejects_out_of_range() {
+        let funding = OutPoint::new(dummy_txid(0x01), 0);
+        let mut tx = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                funding.clone(),
+                Script::new(vec![]),
+                0,
+            )],
+            vec![
+                TransactionOutput::new(MAX_MONEY - 1, Script::new(vec![0x51])),
+                TransactionOutput::new(1, Script::new(vec![0x51])),
+            ],
+            0,
+        );
+        assert_eq!(tx.total_output_value(), Ok(MAX_MONEY));
+
+        tx.outputs[1].value = 2;
+        assert_eq!(tx.total_output_value(), Err(BitcoinError::ValueOutOfRange));
+        tx.outputs[0].value = u64::MAX;
+        assert_eq!(tx.total_output_value(), Err(BitcoinError::ValueOutOfRange));
+
+        let mut prevouts = HashMap::new();
+        prevouts.insert(funding, MAX_MONEY);
+        assert_eq!(tx.fee(&prevouts), Err(BitcoinError::ValueOutOfRange));
+        assert_eq!(tx.fee_rate(&prevouts), Err(BitcoinError::ValueOutOfRange));
+        assert_eq!(
+            package_fee_rate(&[tx], &prevouts),
+            Err(BitcoinError::ValueOutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_clear_input_signature() {
+        let inputs = vec![
+            TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                Script::new(vec![0x01, 0xAA]),
+                0xFFFFFFFF,
+            ),
+            TransactionInput::new(
+                OutPoint::new(dummy_txid(2), 1),
+                Script::new(vec![0x01, 0xBB]),
+                0xFFFFFFFF,
+            ),
+        ];
+        let mut tx = BitcoinTransaction::new(2, inputs.clone(), vec![], 0);
+
+        tx.clear_input_signature(1).unwrap();
+        assert!(tx.inputs[1].script_sig.is_empty());
+        assert_eq!(tx.inputs[1].previous_output, inputs[1].previous_output);
+        assert_eq!(tx.inputs[0], inputs[0]);
+
+        assert_eq!(
+            tx.clear_input_signature(2),
+            Err(BitcoinError::IndexOutOfBounds)
+        );
+    }
+
+    #[test]
+    fn test_is_version_standard() {
+        let tests = vec![
+            (0u32, false),
+            (1, true),
+            (2, true),
+            (3, false),
+            (0xFFFFFFFF, false),
+        ];
+        for (version, standard) in tests {
+            let tx = BitcoinTransaction::new(version, vec![], vec![], 0);
+            assert_eq!(tx.is_version_standard(), standard);
+        }
+    }
+
+    #[test]
+    fn test_min_rbf_replacement_fee() {
+        let tx = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                Script::new(vec![]),
+                0xFFFFFFFD,
+            )],
+            vec![TransactionOutput::new(1000, Script::new(vec![0x51]))],
+            0,
+        );
+        assert_eq!(tx.vsize(), 61);
+        assert_eq!(tx.min_rbf_replacement_fee(500, 1.0), Ok(561));
+        assert_eq!(tx.min_rbf_replacement_fee(500, 1.5), Ok(592));
+        assert_eq!(tx.min_rbf_replacement_fee(500, 0.0), Ok(500));
+
+        assert_eq!(
+            tx.min_rbf_replacement_fee(u64::MAX, 1.0),
+            Err(BitcoinError::ValueOutOfRange)
+        );
+        for rate in [f64::INFINITY, f64::NAN, -1.0, f64::MAX] {
+            assert_eq!(
+                tx.min_rbf_replacement_fee(1, rate),
+                Err(BitcoinError::ValueOutOfRange)
+            );
+        }
+    }
+
+    #[test]
+    fn test_script_instructions() {
+        let mut bytes = vec![0x00, 0x02, 0xAA, 0xBB, 0x4C, 0x01, 0xCC, 0x76];
+        let script = Script::new(bytes.clone());
+        let instructions: Vec<_> = script.instructions().collect();
+        assert_eq!(
+            instructions,
+            vec![
+                Ok(Instruction::PushBytes(&[])),
+                Ok(Instruction::PushBytes(&[0xAA, 0xBB])),
+                Ok(Instruction::PushBytes(&[0xCC])),
+                Ok(Instruction::Op(0x76)),
+            ]
+        );
+
+        bytes.extend_from_slice(&[0x05, 0x01]);
+        let truncated = Script::new(bytes);
+        assert_eq!(
+            truncated.instructions().last(),
+            Some(Err(BitcoinError::InsufficientBytes))
+        );
+        assert_eq!(truncated.pushed_data().len(), 3);
+    }
+
+    #[test]
+    fn test_referenced_hashes() {
+        let mut p2wsh = vec![0x00, 0x20];
+        p2wsh.extend_from_slice(&[0x42; 32]);
+        let tx = BitcoinTransaction::new(
+            2,
+            vec![
+                TransactionInput::new(OutPoint::new(dummy_txid(1), 0), Script::new(vec![]), 0),
+                TransactionInput::new(OutPoint::new(dummy_txid(2), 3), Script::new(vec![]), 0),
+            ],
+            vec![TransactionOutput::new(1000, Script::new(p2wsh))],
+            0,
+        );
+        let hashes = tx.referenced_hashes();
+        assert_eq!(hashes.len(), 3);
+        assert!(hashes.contains(&dummy_txid(1)));
+        assert!(hashes.contains(&dummy_txid(2)));
+        assert!(hashes.contains(&[0x42; 32]));
+    }
+
+    #[test]
+    fn test_build_block_filter_testnet_genesis() {
+        // BIP158 test vector for testnet3 block 0, whose only script is the coinbase output.
+        let mut block_hash: [u8; 32] =
+            hex::decode("000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        block_hash.reverse();
+        let coinbase_script = Script::new(
+            hex::decode(
+                "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac",
+            )
+            .unwrap(),
+        );
+
+        let filter = build_block_filter(&[coinbase_script], &block_hash);
+        assert_eq!(hex::encode(filter), "019dfca8");
+        assert_eq!(build_block_filter(&[], &block_hash), vec![0x00]);
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let block_hash = dummy_txid(0x77);
+        let scripts: Vec<Script> = (0u8..20)
+            .map(|i| Script::new(vec![0x00, 0x14, i, i, i]))
+            .collect();
+        let filter = build_block_filter(&scripts, &block_hash);
+
+        assert!(filter_matches(&filter, &block_hash, &[&scripts[7].bytes]));
+        assert!(filter_matches(
+            &filter,
+            &block_hash,
+            &[&[0xDE, 0xAD], &scripts[19].bytes]
+        ));
+        assert!(!filter_matches(&filter, &block_hash, &[&[0x51]]));
+        assert!(!filter_matches(&filter, &block_hash, &[]));
+        assert!(!filter_matches(&[0x00], &block_hash, &[&scripts[0].bytes]));
+    }
+
+    #[test]
+    fn test_sort_for_block() {
+        let parent = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                Script::new(vec![]),
+                0,
+            )],
+            vec![TransactionOutput::new(50_000, Script::new(vec![0x51]))],
+            0,
+        );
+        let child = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                OutPoint::new(parent.txid().0, 0),
+                Script::new(vec![]),
+                0,
+            )],
+            vec![TransactionOutput::new(40_000, Script::new(vec![0x51]))],
+            0,
+        );
+        let standalone = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(2), 0),
+                Script::new(vec![]),
+                0,
+            )],
+            vec![TransactionOutput::new(10_000, Script::new(vec![0x52]))],
+            0,
+        );
+
+        let mut txs = vec![
+            (child.clone(), 10_000, 100),
+            (standalone.clone(), 5_000, 100),
+            (parent.clone(), 100, 100),
+        ];
+        sort_for_block(&mut txs);
+        let order: Vec<_> = txs.into_iter().map(|(tx, _, _)| tx).collect();
+        assert_eq!(order, vec![standalone, parent, child]);
+    }
+
+    #[test]
+    fn test_dependency_edges() {
+        let spend = |txid: Txid, value: u64| {
+            BitcoinTransaction::new(
+                2,
+                vec![TransactionInput::new(
+                    OutPoint::new(txid.0, 0),
+                    Script::new(vec![]),
+                    0,
+                )],
+                vec![TransactionOutput::new(value, Script::new(vec![0x51]))],
+                0,
+            )
+        };
+        let first = spend(Txid(dummy_txid(9)), 3000);
+        let second = spend(first.txid(), 2000);
+        let third = spend(second.txid(), 1000);
+
+        let edges = dependency_edges(&[third.clone(), first.clone(), second.clone()]);
+        assert_eq!(edges, vec![(2, 0), (1, 2)]);
+        assert!(dependency_edges(&[first, third]).is_empty());
+    }
+
+    #[test]
+    fn test_ancestors_and_descendants_diamond() {
+        let spend = |parents: &[(Txid, u32)], value: u64| {
+            let inputs = parents
+                .iter()
+                .map(|(txid, vout)| {
+                    TransactionInput::new(OutPoint::new(txid.0, *vout), Script::new(vec![]), 0)
+                })
+                .collect();
+            BitcoinTransaction::new(
+                2,
+                inputs,
+                vec![
+                    TransactionOutput::new(value, Script::new(vec![0x51])),
+                    TransactionOutput::new(value, Script::new(vec![0x52])),
+                ],
+                0,
+            )
+        };
+        let top = spend(&[(Txid(dummy_txid(1)), 0)], 4000);
+        let left = spend(&[(top.txid(), 0)], 1500);
+        let right = spend(&[(top.txid(), 1)], 1400);
+        let bottom = spend(&[(left.txid(), 0), (right.txid(), 0)], 1000);
+        let unrelated = spend(&[(Txid(dummy_txid(2)), 0)], 500);
+        let txs = vec![bottom, right, unrelated, top, left];
+
+        assert_eq!(ancestors(&txs, 0), HashSet::from([1, 3, 4]));
+        assert_eq!(descendants(&txs, 3), HashSet::from([0, 1, 4]));
+        assert_eq!(descendants(&txs, 1), HashSet::from([0]));
+        assert!(ancestors(&txs, 2).is_empty());
+    }
+
+    #[test]
+    fn test_encode_all() {
+        let txs: Vec<BitcoinTransaction> = (0u8..3)
+            .map(|i| {
+                BitcoinTransaction::new(
+                    2,
+                    vec![TransactionInput::new(
+                        OutPoint::new(dummy_txid(i), i as u32),
+                        Script::new(vec![i; 300]),
+                        0xFFFFFFFF,
+                    )],
+                    vec![TransactionOutput::new(
+                        1000 * i as u64,
+                        Script::new(vec![0x51]),
+                    )],
+                    i as u32,
+                )
+            })
+            .collect();
+
+        let expected: Vec<u8> = txs.iter().flat_map(|tx| tx.to_bytes()).collect();
+        let encoded = encode_all(&txs);
+        assert_eq!(encoded, expected);
+        for tx in &txs {
+            assert_eq!(tx.serialized_len(), tx.to_bytes().len());
+        }
+        assert!(encode_all(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_from_bytes_with_script_offsets() {
+        let tx = BitcoinTransaction::new(
+            1,
+            vec![
+                TransactionInput::new(
+                    OutPoint::new(dummy_txid(1), 0),
+                    Script::new(vec![0xAA; 3]),
+                    0xFFFFFFFF,
+                ),
+                TransactionInput::new(
+                    OutPoint::new(dummy_txid(2), 1),
+                    Script::new(vec![0xBB; 300]),
+                    0xFFFFFFFF,
+                ),
+            ],
+            vec![TransactionOutput::new(1000, Script::new(vec![0xCC, 0xDD]))],
+            0,
+        );
+        let bytes = tx.to_bytes();
+
+        let (parsed, offsets) = BitcoinTransaction::from_bytes_with_script_offsets(&bytes).unwrap();
+        assert_eq!(parsed, tx);
+        assert_eq!(offsets.len(), 3);
+        assert_eq!(
+            &bytes[offsets[0].clone()],
+            &tx.inputs[0].script_sig.bytes[..]
+        );
+        assert_eq!(
+            &bytes[offsets[1].clone()],
+            &tx.inputs[1].script_sig.bytes[..]
+        );
+        assert_eq!(
+            &bytes[offsets[2].clone()],
+            &tx.outputs[0].script_pubkey.bytes[..]
+        );
+    }
+
+    #[test]
+    fn test_txid_prefix8() {
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
+        bytes[8] = 0xFF;
+        assert_eq!(Txid(bytes).prefix8(), 0x0807060504030201);
+
+        let tx = BitcoinTransaction::new(1, vec![], vec![], 0);
+        assert_eq!(tx.txid_prefix8(), tx.txid().prefix8());
+    }
+
+    #[test]
+    fn test_truc_limits() {
+        let truc_with_script = |len: usize| {
+            BitcoinTransaction::new(
+                3,
+                vec![TransactionInput::new(
+                    OutPoint::new(dummy_txid(1), 0),
+                    Script::new(vec![0x51; len]),
+                    0xFFFFFFFF,
+                )],
+                vec![TransactionOutput::new(0, Script::new(vec![0x51]))],
+                0,
+            )
+        };
+        let small = truc_with_script(100);
+        assert!(small.is_truc());
+        assert!(!small.violates_truc_limits(0));
+        assert!(!small.violates_truc_limits(1));
+        assert!(small.violates_truc_limits(2));
+
+        let medium = truc_with_script(2_000);
+        assert!(!medium.violates_truc_limits(0));
+        assert!(medium.violates_truc_limits(1));
+
+        let large = truc_with_script(TRUC_MAX_VSIZE);
+        assert!(large.violates_truc_limits(0));
+
+        let v2 = BitcoinTransaction::new(2, large.inputs.clone(), large.outputs.clone(), 0);
+        assert!(!v2.is_truc());
+        assert!(!v2.violates_truc_limits(5));
+    }
+
+    #[test]
+    fn test_script_type() {
+        let mut p2sh = vec![0xA9, 0x14];
+        p2sh.extend_from_slice(&[0x11; 20]);
+        p2sh.push(0x87);
+        let mut p2tr = vec![0x51, 0x20];
+        p2tr.extend_from_slice(&[0x22; 32]);
+        let mut multisig = vec![0x51, 0x21];
+        multisig.extend_from_slice(&[0x02; 33]);
+        multisig.extend_from_slice(&[0x51, 0xAE]);
+
+        assert_eq!(p2pkh_script(1).script_type(), ScriptType::P2pkh);
+        assert_eq!(p2wpkh_script(1).script_type(), ScriptType::P2wpkh);
+        assert_eq!(Script::new(p2sh).script_type(), ScriptType::P2sh);
+        assert_eq!(Script::new(p2tr).script_type(), ScriptType::P2tr);
+        assert_eq!(Script::new(multisig).script_type(), ScriptType::Multisig);
+        assert_eq!(
+            Script::new(vec![0x6A, 0x01, 0x00]).script_type(),
+            ScriptType::OpReturn
+        );
+        assert_eq!(
+            Script::new(vec![0x51]).script_type(),
+            ScriptType::NonStandard
+        );
+    }
+
+    #[test]
+    fn test_structure_fingerprint() {
+        let build = |txid: u8, value: u64, tag: u8| {
+            BitcoinTransaction::new(
+                2,
+                vec![TransactionInput::new(
+                    OutPoint::new(dummy_txid(txid), 0),
+                    Script::new(vec![]),
+                    0xFFFFFFFD,
+                )],
+                vec![
+                    TransactionOutput::new(value, p2wpkh_script(tag)),
+                    TransactionOutput::new(value * 2, p2pkh_script(tag)),
+                ],
+                800_000,
+            )
+        };
+        let a = build(1, 1000, 0xAA);
+        let b = build(2, 5000, 0xBB);
+        assert_eq!(a.structure_fingerprint(), b.structure_fingerprint());
+
+        let fingerprint = a.structure_fingerprint();
+        assert_eq!(fingerprint.input_count, 1);
+        assert_eq!(
+            fingerprint.output_types,
+            vec![ScriptType::P2pkh, ScriptType::P2wpkh]
+        );
+        assert!(fingerprint.uses_lock_time);
+        assert!(fingerprint.signals_rbf);
+        assert!(fingerprint.bip69_sorted);
+
+        let mut reordered = a.clone();
+        reordered.outputs.reverse();
+        assert_ne!(reordered.structure_fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn test_set_output_value() {
+        let mut tx = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                Script::new(vec![]),
+                0,
+            )],
+            vec![
+                TransactionOutput::new(50_000, p2wpkh_script(0x01)),
+                TransactionOutput::new(49_000, p2wpkh_script(0x02)),
+            ],
+            0,
+        );
+        let original = tx.clone();
+
+        tx.set_output_value(1, 48_500).unwrap();
+        assert_eq!(tx.outputs[0], original.outputs[0]);
+        assert_eq!(tx.outputs[1].value, 48_500);
+        let (parsed, _) = BitcoinTransaction::from_bytes(&tx.to_bytes()).unwrap();
+        assert_eq!(parsed.outputs[1].value, 48_500);
+
+        assert_eq!(
+            tx.set_output_value(2, 1),
+            Err(BitcoinError::IndexOutOfBounds)
+        );
+        assert_eq!(
+            tx.set_output_value(0, MAX_MONEY + 1),
+            Err(BitcoinError::ValueOutOfRange)
+        );
+        assert_eq!(tx.outputs[0].value, 50_000);
+    }
+
+    #[test]
+    fn test_consolidation_savings() {
+        assert_eq!(
+            consolidation_savings(10, ScriptType::P2wpkh, 50.0, 5.0),
+            30_600
+        );
+        assert_eq!(consolidation_savings(2, ScriptType::P2pkh, 1.0, 3.0), -592);
+        assert_eq!(
+            consolidation_savings(10, ScriptType::OpReturn, 50.0, 5.0),
+            0
+        );
+    }
+
+    #[test]
+    fn test_looks_like_payjoin() {
+        let mut p2pkh_sig = vec![0x47];
+        p2pkh_sig.extend_from_slice(&[0x30; 71]);
+        p2pkh_sig.push(0x21);
+        p2pkh_sig.extend_from_slice(&[0x02; 33]);
+        let sender_input = TransactionInput::new(
+            OutPoint::new(dummy_txid(1), 0),
+            Script::new(p2pkh_sig),
+            0xFFFFFFFD,
+        );
+        let receiver_input = TransactionInput::new(
+            OutPoint::new(dummy_txid(2), 1),
+            Script::new(vec![]),
+            0xFFFFFFFD,
+        );
+        assert_eq!(sender_input.inferred_spend_type(), Some(ScriptType::P2pkh));
+        assert_eq!(receiver_input.inferred_spend_type(), None);
+
+        let outputs = vec![
+            TransactionOutput::new(150_000, p2wpkh_script(0x01)),
+            TransactionOutput::new(42_000, p2pkh_script(0x02)),
+        ];
+        let payjoin = BitcoinTransaction::new(
+            2,
+            vec![sender_input.clone(), receiver_input.clone()],
+            outputs.clone(),
+            0,
+        );
+        assert!(payjoin.looks_like_payjoin());
+
+        let single_party = BitcoinTransaction::new(
+            2,
+            vec![receiver_input.clone(), receiver_input],
+            outputs.clone(),
+            0,
+        );
+        assert!(!single_party.looks_like_payjoin());
+
+        let simple_spend = BitcoinTransaction::new(2, vec![sender_input], outputs, 0);
+        assert!(!simple_spend.looks_like_payjoin());
+    }
+
+    #[test]
+    fn test_skeleton() {
+        let tx = BitcoinTransaction::new(
+            1,
+            vec![
+                TransactionInput::new(
+                    OutPoint::new(dummy_txid(1), 0),
+                    Script::new(vec![0x01, 0xAA]),
+                    0xFFFFFFFF,
+                ),
+                TransactionInput::new(
+                    OutPoint::new(dummy_txid(2), 4),
+                    Script::new(vec![0x02, 0xBB, 0xCC]),
+                    0xFFFFFFFE,
+                ),
+            ],
+            vec![TransactionOutput::new(1000, p2wpkh_script(0x01))],
+            7,
+        );
+        let skeleton = tx.skeleton();
+        assert!(
+            skeleton
+                .inputs
+                .iter()
+                .all(|input| input.script_sig.is_empty())
+        );
+        for (stripped, original) in skeleton.inputs.iter().zip(&tx.inputs) {
+            assert_eq!(stripped.previous_output, original.previous_output);
+            assert_eq!(stripped.sequence, original.sequence);
+        }
+        assert_eq!(skeleton.outputs, tx.outputs);
+        assert_eq!(skeleton.lock_time, tx.lock_time);
+    }
+
+    #[test]
+    fn test_burned_value() {
+        let tx = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                Script::new(vec![]),
+                0,
+            )],
+            vec![
+                TransactionOutput::new(
+                    5_000,
+                    Script::new(vec![0x6A, 0x04, 0xDE, 0xAD, 0xBE, 0xEF]),
+                ),
+                TransactionOutput::new(20_000, p2wpkh_script(0x01)),
+                TransactionOutput::new(300, Script::new(vec![0x51; MAX_SCRIPT_SIZE + 1])),
+            ],
+            0,
+        );
+        assert!(tx.outputs[0].script_pubkey.is_provably_unspendable());
+        assert!(!tx.outputs[1].script_pubkey.is_provably_unspendable());
+        assert_eq!(tx.burned_value(), 5_300);
+
+        let mut huge = tx.clone();
+        huge.outputs[0].value = u64::MAX;
+        assert_eq!(huge.burned_value(), u64::MAX);
+    }
+
+    #[test]
+    fn test_minimize_failing_input() {
+        let tx = BitcoinTransaction::new(
+            1,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                Script::new(vec![0x51; 10]),
+                0xFFFFFFFF,
+            )],
+            vec![],
+            0,
+        );
+        let mut malformed = tx.to_bytes();
+        malformed.truncate(malformed.len() - 2);
+        let expected = BitcoinTransaction::from_bytes(&malformed).unwrap_err();
+
+        let minimized = minimize_failing_input(&malformed);
+        assert!(minimized.len() < malformed.len());
+        assert!(malformed.starts_with(&minimized));
+        assert_eq!(
+            BitcoinTransaction::from_bytes(&minimized).unwrap_err(),
+            expected
+        );
+        assert_eq!(expected, BitcoinError::InsufficientBytes);
+        assert!(minimized.is_empty());
+
+        // A non-minimal input count shrinks to the version plus that CompactSize.
+        let mut non_minimal = tx.to_bytes();
+        non_minimal.splice(4..5, [0xFD, 0x01, 0x00]);
+        let mut expected_prefix = 1u32.to_le_bytes().to_vec();
+        expected_prefix.extend_from_slice(&[0xFD, 0x01, 0x00]);
+        assert_eq!(minimize_failing_input(&non_minimal), expected_prefix);
+
+        let valid = tx.to_bytes();
+        assert_eq!(minimize_failing_input(&valid), valid);
+    }
+
+    #[test]
+    fn test_standard_witness_program_size() {
+        let program = |version_op: u8, len: usize| {
+            let mut bytes = vec![version_op, len as u8];
+            bytes.extend(std::iter::repeat_n(0x33, len));
+            Script::new(bytes)
+        };
+        assert!(program(0x00, 32).has_standard_witness_program_size());
+        assert!(program(0x00, 20).has_standard_witness_program_size());
+        assert!(program(0x51, 32).has_standard_witness_program_size());
+        assert!(!program(0x00, 22).has_standard_witness_program_size());
+        assert!(!program(0x51, 20).has_standard_witness_program_size());
+        assert!(!program(0x52, 32).has_standard_witness_program_size());
+        assert!(!p2pkh_script(0x01).has_standard_witness_program_size());
+    }
+
+    #[test]
+    fn test_hash_primitives() {
+        assert_eq!(
+            hex::encode(hash256(b"")),
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456"
+        );
+        assert_eq!(
+            hex::encode(hash160(b"")),
+            "b472a266d0bd89c13706a4132ccfb16f7c3b9fcb"
+        );
+        assert_eq!(
+            hex::encode(hash256(b"hello")),
+            "9595c9df90075148eb06860365df33584b75bff782a510c6cd4883a419833d50"
+        );
+    }
+
+    #[test]
+    fn test_script_push_builders() {
+        let mut script = Script::new(vec![]);
+        script.push_int(0);
+        script.push_int(16);
+        script.push_int(-1);
+        script.push_int(17);
+        script.push_int(128);
+        script.push_int(-255);
+        script.push_data(&[0xAB; 80]);
+        assert_eq!(
+            script.bytes[..15],
+            [
+                0x00, 0x60, 0x4F, 0x01, 0x11, 0x02, 0x80, 0x00, 0x02, 0xFF, 0x80, 0x4C, 0x50, 0xAB,
+                0xAB
+            ]
+        );
+        assert_eq!(script.pushed_data().last().unwrap(), &[0xAB; 80]);
+    }
+
+    #[test]
+    fn test_multisig_redeem_script() {
+        let keys: Vec<Vec<u8>> = (1u8..=3).map(|i| vec![0x02 + (i % 2); 33]).collect();
+        let script = multisig_redeem_script(2, &keys).unwrap();
+        assert_eq!(script.len(), 1 + 3 * 34 + 2);
+        assert_eq!(script[0], 0x52);
+        assert_eq!(script[script.len() - 2..], [0x53, 0xAE]);
+        assert_eq!(script.script_type(), ScriptType::Multisig);
+
+        assert_eq!(
+            multisig_redeem_script(4, &keys),
+            Err(BitcoinError::InvalidFormat)
+        );
+        assert_eq!(
+            multisig_redeem_script(0, &keys),
+            Err(BitcoinError::InvalidFormat)
+        );
+        assert_eq!(
+            multisig_redeem_script(1, &[vec![0x02; 32]]),
+            Err(BitcoinError::InvalidFormat)
+        );
+    }
+
+    #[test]
+    fn test_p2sh_and_p2wsh_wrapping() {
+        let keys: Vec<Vec<u8>> = [0x11, 0x22, 0x33]
+            .iter()
+            .map(|&b| {
+                let mut key = vec![0x02];
+                key.extend_from_slice(&[b; 32]);
+                key
+            })
+            .collect();
+        let redeem = multisig_redeem_script(2, &keys).unwrap();
+
+        let p2sh = redeem.to_p2sh();
+        assert_eq!(
+            hex::encode(&p2sh.bytes),
+            "a9145cc52612d829ae9145a33d9ef5fe2f5c31e6062787"
+        );
+        assert_eq!(p2sh.script_type(), ScriptType::P2sh);
+
+        let p2wsh = redeem.to_p2wsh();
+        assert_eq!(
+            hex::encode(&p2wsh.bytes),
+            "0020a409fc2d6aade26b78641dcfac50abe7543e5cc7bb910c09a6024ba3e561d8e8"
+        );
+        assert_eq!(p2wsh.script_type(), ScriptType::P2wsh);
+    }
+
+    #[test]
+    fn test_duplicate_input_inflation_risk() {
+        // Shape of the CVE-2018-17144 trigger: the same outpoint spent twice in one transaction.
+        let input = TransactionInput::new(OutPoint::new(dummy_txid(1), 0), Script::new(vec![]), 0);
+        let output = TransactionOutput::new(2 * 50_000, p2wpkh_script(0x01));
+        let crafted = BitcoinTransaction::new(
+            1,
+            vec![input.clone(), input.clone()],
+            vec![output.clone()],
+            0,
+        );
+        assert!(crafted.has_duplicate_inputs());
+        assert!(crafted.has_duplicate_input_inflation_risk());
+
+        let other = TransactionInput::new(OutPoint::new(dummy_txid(1), 1), Script::new(vec![]), 0);
+        let honest = BitcoinTransaction::new(1, vec![input, other], vec![output], 0);
+        assert!(!honest.has_duplicate_input_inflation_risk());
+    }
+
+    #[test]
+    fn test_output_vsize_for_script() {
+        let p2wpkh = p2wpkh_script(0x01);
+        assert_eq!(output_vsize_for_script(&p2wpkh), 31);
+        assert_eq!(
+            output_vsize_for_script(&p2wpkh),
+            TransactionOutput::new(0, p2wpkh).serialized_len()
+        );
+        assert_eq!(output_vsize_for_script(&p2pkh_script(0x01)), 34);
+    }
+
+    #[test]
+    fn test_from_rpc_json() {
+        let json = serde_json::json!({
+            "txid": "ignored",
+            "version": 2,
+            "locktime": 650000,
+            "vin": [
+                {
+                    "txid": "0100000000000000000000000000000000000000000000000000000000000000",
+                    "vout": 3,
+                    "scriptSig": { "asm": "", "hex": "0102" },
+                    "sequence": 4294967293u32
+                }
+            ],
+            "vout": [
+                {
+                    "value": 0.5,
+                    "n": 0,
+                    "scriptPubKey": { "hex": hex::encode(p2wpkh_script(0x01).bytes) }
+                },
+                {
+                    "value": 0.00012345,
+                    "n": 1,
+                    "scriptPubKey": { "hex": "6a0100" }
+                }
+            ]
+        });
+        let expected = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 3),
+                Script::new(vec![0x01, 0x02]),
+                0xFFFFFFFD,
+            )],
+            vec![
+                TransactionOutput::new(50_000_000, p2wpkh_script(0x01)),
+                TransactionOutput::new(12_345, Script::new(vec![0x6A, 0x01, 0x00])),
+            ],
+            650000,
+        );
+        let parsed = BitcoinTransaction::from_rpc_json(&json).unwrap();
+        assert_eq!(parsed.to_bytes(), expected.to_bytes());
+
+        let coinbase = serde_json::json!({
+            "version": 1,
+            "locktime": 0,
+            "vin": [{ "coinbase": "03a08601", "sequence": 4294967295u32 }],
+            "vout": []
+        });
+        let parsed = BitcoinTransaction::from_rpc_json(&coinbase).unwrap();
+        assert_eq!(
+            parsed.inputs[0].previous_output,
+            OutPoint::new([0; 32], 0xFFFFFFFF)
+        );
+        assert_eq!(
+            parsed.inputs[0].script_sig.bytes,
+            vec![0x03, 0xA0, 0x86, 0x01]
+        );
+
+        let missing_vout = serde_json::json!({ "version": 1, "locktime": 0, "vin": [] });
+        assert_eq!(
+            BitcoinTransaction::from_rpc_json(&missing_vout),
+            Err(BitcoinError::InvalidFormat)
+        );
+    }
+
+    #[test]
+    fn test_tx_size_histogram() {
+        let with_script_len = |len: usize| {
+            BitcoinTransaction::new(
+                2,
+                vec![TransactionInput::new(
+                    OutPoint::new(dummy_txid(1), 0),
+                    Script::new(vec![0x51; len]),
+                    0xFFFFFFFF,
+                )],
+                vec![TransactionOutput::new(1000, p2wpkh_script(0x01))],
+                0,
+            )
+        };
+        let txs: Vec<_> = [10, 100, 300, 600, 2_000]
+            .iter()
+            .map(|&len| with_script_len(len))
+            .collect();
+        let histogram = tx_size_histogram(&txs);
+        let expected: BTreeMap<usize, usize> = [(0, 2), (250, 1), (500, 1), (1000, 1)]
+            .into_iter()
+            .collect();
+        assert_eq!(histogram, expected);
+        assert_eq!(tx_size_histogram(&[]).values().sum::<usize>(), 0);
+
+        // Each bucket includes its lower bound and excludes the next one.
+        let below = with_script_len(915);
+        let on_boundary = with_script_len(916);
+        assert_eq!(below.vsize(), 999);
+        assert_eq!(on_boundary.vsize(), 1000);
+        let histogram = tx_size_histogram(&[below, on_boundary]);
+        assert_eq!(histogram[&500], 1);
+        assert_eq!(histogram[&1000], 1);
+    }
+
+    #[test]
+    fn test_is_sweep() {
+        let inputs: Vec<_> = (1u8..=3)
+            .map(|i| TransactionInput::new(OutPoint::new(dummy_txid(i), 0), Script::new(vec![]), 0))
+            .collect();
+        let destination = TransactionOutput::new(90_000, p2wpkh_script(0x01));
+        let change = TransactionOutput::new(5_000, p2wpkh_script(0x02));
+
+        let sweep = BitcoinTransaction::new(2, inputs.clone(), vec![destination.clone()], 0);
+        assert!(sweep.is_sweep());
+
+        let with_change = BitcoinTransaction::new(2, inputs, vec![destination.clone(), change], 0);
+        assert!(!with_change.is_sweep());
+
+        let no_inputs = BitcoinTransaction::new(2, vec![], vec![destination], 0);
+        assert!(!no_inputs.is_sweep());
+    }
+
+    #[test]
+    fn test_max_stack_estimate() {
+        let p2pkh = p2pkh_script(0x01);
+        assert_eq!(p2pkh.max_stack_estimate(), 2);
+
+        let keys: Vec<Vec<u8>> = (0u8..15).map(|i| vec![0x02 | (i & 1); 33]).collect();
+        let multisig = multisig_redeem_script(11, &keys).unwrap();
+        assert_eq!(multisig.max_stack_estimate(), 17);
+        assert!(multisig.max_stack_estimate() > p2pkh.max_stack_estimate());
+
+        assert_eq!(Script::new(vec![]).max_stack_estimate(), 0);
+    }
+
+    #[test]
+    fn test_has_valid_pubkey_encodings() {
+        let push_key = |key: Vec<u8>| {
+            let mut script = Script::new(vec![]);
+            script.push_data(&key);
+            script.push_opcode(0xAC);
+            script
+        };
+        assert!(push_key(vec![0x03; 33]).has_valid_pubkey_encodings());
+        assert!(push_key(vec![0x04; 65]).has_valid_pubkey_encodings());
+        assert!(!push_key(vec![0x05; 33]).has_valid_pubkey_encodings());
+        assert!(!push_key(vec![0x02; 65]).has_valid_pubkey_encodings());
+        assert!(p2pkh_script(0x09).has_valid_pubkey_encodings());
+    }
+
+    #[test]
+    fn test_fee_delta() {
+        let inputs = vec![
+            TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                Script::new(vec![]),
+                0xFFFFFFFD,
+            ),
+            TransactionInput::new(
+                OutPoint::new(dummy_txid(2), 1),
+                Script::new(vec![]),
+                0xFFFFFFFD,
+            ),
+        ];
+        let prevouts = vec![
+            TransactionOutput::new(60_000, p2wpkh_script(0x01)),
+            TransactionOutput::new(40_000, p2wpkh_script(0x02)),
+        ];
+        let original = BitcoinTransaction::new(
+            2,
+            inputs.clone(),
+            vec![
+                TransactionOutput::new(70_000, p2wpkh_script(0x03)),
+                TransactionOutput::new(29_000, p2wpkh_script(0x04)),
+            ],
+            0,
+        );
+        let mut bumped = original.clone();
+        bumped.set_output_value(1, 26_500).unwrap();
+
+        assert_eq!(original.fee_from_prevouts(&prevouts), Ok(1_000));
+        assert_eq!(bumped.fee_delta(&original, &prevouts), Ok(2_500));
+        assert_eq!(original.fee_delta(&bumped, &prevouts), Ok(-2_500));
+        assert_eq!(
+            bumped.fee_delta(&original, &prevouts[..1]),
+            Err(BitcoinError::MissingPrevout)
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_with_warnings() {
+        let tx = BitcoinTransaction::new(
+            1,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                // A single 0x05 pushed with a direct push instead of OP_5.
+                Script::new(vec![0x01, 0x05]),
+                0xFFFFFFFF,
+            )],
+            vec![TransactionOutput::new(1000, p2wpkh_script(0x01))],
+            0,
+        );
+        let (_, warnings) = BitcoinTransaction::from_bytes_with_warnings(&tx.to_bytes()).unwrap();
+        assert_eq!(warnings, vec![DecodeWarning::NonMinimalPush { offset: 42 }]);
+
+        // Re-encode the input count (1) as 0xFD 0x01 0x00.
+        let mut bytes = tx.to_bytes();
+        bytes.splice(4..5, [0xFD, 0x01, 0x00]);
+        let (parsed, warnings) = BitcoinTransaction::from_bytes_with_warnings(&bytes).unwrap();
+        assert_eq!(parsed, tx);
+        assert_eq!(
+            warnings,
+            vec![
+                DecodeWarning::NonCanonicalCompactSize { offset: 4 },
+                DecodeWarning::NonMinimalPush { offset: 44 },
+            ]
+        );
+
+        let clean = BitcoinTransaction::new(1, vec![], vec![], 0);
+        let (_, warnings) =
+            BitcoinTransaction::from_bytes_with_warnings(&clean.to_bytes()).unwrap();
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_is_final_at() {
+        let with_lock_time = |lock_time: u32, sequence: u32| {
+            BitcoinTransaction::new(
+                2,
+                vec![TransactionInput::new(
+                    OutPoint::new(dummy_txid(1), 0),
+                    Script::new(vec![]),
+                    sequence,
+                )],
+                vec![TransactionOutput::new(1000, p2wpkh_script(0x01))],
+                lock_time,
+            )
+        };
+        let height_locked = with_lock_time(800_000, 0xFFFFFFFE);
+        assert_eq!(height_locked.lock_time_kind(), LockTime::Height(800_000));
+        assert!(height_locked.is_lock_time_enabled());
+        assert!(!height_locked.is_final_at(799_999, 0));
+        assert!(!height_locked.is_final_at(800_000, 0));
+        assert!(height_locked.is_final_at(800_001, 0));
+
+        let time_locked = with_lock_time(1_700_000_000, 0xFFFFFFFE);
+        assert_eq!(time_locked.lock_time_kind(), LockTime::Time(1_700_000_000));
+        assert!(!time_locked.is_final_at(900_000, 1_700_000_000));
+        assert!(time_locked.is_final_at(0, 1_700_000_001));
+
+        assert!(with_lock_time(800_000, 0xFFFFFFFF).is_final_at(1, 0));
+        assert!(with_lock_time(0, 0).is_final_at(0, 0));
+    }
+
+    #[test]
+    fn test_outpoint_ordering() {
+        let mut outpoints = vec![
+            OutPoint::new(dummy_txid(2), 0),
+            OutPoint::new(dummy_txid(1), 5),
+            OutPoint::new(dummy_txid(1), 1),
+            OutPoint::new([0xFF; 32], 0),
+        ];
+        outpoints.sort();
+        assert_eq!(
+            outpoints,
+            vec![
+                OutPoint::new(dummy_txid(1), 1),
+                OutPoint::new(dummy_txid(1), 5),
+                OutPoint::new(dummy_txid(2), 0),
+                OutPoint::new([0xFF; 32], 0),
+            ]
+        );
+
+        let set: std::collections::BTreeSet<OutPoint> = outpoints.iter().rev().cloned().collect();
+        assert_eq!(set.into_iter().collect::<Vec<_>>(), outpoints);
+    }
+
+    #[test]
+    fn test_estimate_confirmation_blocks() {
+        let tx = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                Script::new(vec![]),
+                0,
+            )],
+            vec![TransactionOutput::new(90_000, p2wpkh_script(0x01))],
+            0,
+        );
+        assert_eq!(tx.vsize(), 82);
+        let table = [(1.0, 144), (25.0, 2), (10.0, 6), (50.0, 1)];
+
+        // 1640 sats over 82 vB is 20 sat/vB.
+        let prevouts = vec![TransactionOutput::new(91_640, p2wpkh_script(0x02))];
+        assert_eq!(tx.estimate_confirmation_blocks(&prevouts, &table), 6);
+
+        let cheap = vec![TransactionOutput::new(90_010, p2wpkh_script(0x02))];
+        assert_eq!(tx.estimate_confirmation_blocks(&cheap, &table), u32::MAX);
+        assert_eq!(tx.estimate_confirmation_blocks(&[], &table), u32::MAX);
+    }
+
+    #[test]
+    fn test_has_output() {
+        let target = p2wpkh_script(0xEE);
+        let tx = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                Script::new(vec![]),
+                0,
+            )],
+            vec![
+                TransactionOutput::new(100_000, p2wpkh_script(0x01)),
+                TransactionOutput::new(25_000, target.clone()),
+            ],
+            0,
+        );
+        assert!(tx.has_output(&target, 25_000));
+        assert!(!tx.has_output(&target, 25_001));
+        assert!(!tx.has_output(&p2wpkh_script(0x02), 1));
+    }
+
+    #[test]
+    fn test_bitcoin_tx_output_count_roundtrips() {
+        let input = TransactionInput::new(
+            OutPoint::new(dummy_txid(1), 0),
+            Script::new(vec![0x01, 0x02]),
+            0xFFFFFFFF,
+        );
+
+        let no_outputs = BitcoinTransaction::new(1, vec![input.clone()], vec![], 0);
+        let bytes = no_outputs.to_bytes();
+        let (parsed, consumed) = BitcoinTransaction::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, no_outputs);
+        assert_eq!(consumed, bytes.len());
+
+        let two_outputs = BitcoinTransaction::new(
+            1,
+            vec![input],
+            vec![
+                TransactionOutput::new(1_500, p2wpkh_script(0x01)),
+                TransactionOutput::new(2_500, p2pkh_script(0x02)),
+            ],
+            0,
+        );
+        let bytes = two_outputs.to_bytes();
+        let (parsed, consumed) = BitcoinTransaction::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, two_outputs);
+        assert_eq!(consumed, bytes.len());
+
+        let display = format!("{}", two_outputs);
+        assert!(display.contains("Value: 2500"));
+        assert!(display.contains("Length: 25"));
+    }
+
+    #[test]
+    fn test_bitcoin_tx_output_count_exceeds_buffer() {
+        let tx = BitcoinTransaction::new(
+            1,
+            vec![],
+            vec![TransactionOutput::new(1_000, p2wpkh_script(0x01))],
+            0,
+        );
+        let mut bytes = tx.to_bytes();
+        // Claim three outputs while only one is present.
+        bytes[5] = 0x03;
+        assert_eq!(
+            BitcoinTransaction::from_bytes(&bytes),
+            Err(BitcoinError::InsufficientBytes)
+        );
+    }
+
+    #[test]
+    fn test_txid_genesis_coinbase() {
+        let bytes = hex::decode(
+            "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000",
+        )
+        .unwrap();
+        let (tx, _) = BitcoinTransaction::from_bytes(&bytes).unwrap();
+        let txid = tx.txid();
+        assert_eq!(
+            txid.to_display_string(),
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+        );
+        assert_eq!(
+            serde_json::to_string(&txid).unwrap(),
+            "\"3ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a\""
+        );
+    }
+
+    #[test]
+    fn test_serialize_into_slice() {
+        let tx = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                Script::new(vec![0x51; 300]),
+                0xFFFFFFFF,
+            )],
+            vec![TransactionOutput::new(1_000, p2wpkh_script(0x01))],
+            0,
+        );
+        let expected = tx.to_bytes();
+
+        let mut exact = [0u8; 420];
+        let buf = &mut exact[..expected.len()];
+        assert_eq!(tx.serialize_into_slice(buf), Ok(expected.len()));
+        assert_eq!(buf, &expected[..]);
+
+        let mut roomy = [0xAAu8; 512];
+        assert_eq!(tx.serialize_into_slice(&mut roomy), Ok(expected.len()));
+        assert_eq!(&roomy[..expected.len()], &expected[..]);
+        assert_eq!(roomy[expected.len()], 0xAA);
+
+        let mut small = [0u8; 16];
+        assert_eq!(
+            tx.serialize_into_slice(&mut small),
+            Err(BitcoinError::InsufficientBytes)
+        );
+    }
+
+    #[test]
+    fn test_compact_size_rejects_non_minimal() {
+        let non_minimal: Vec<Vec<u8>> = vec![
+            vec![0xFD, 0x05, 0x00],
+            vec![0xFD, 0xFC, 0x00],
+            vec![0xFE, 0xFF, 0xFF, 0x00, 0x00],
+            vec![0xFF, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00],
+        ];
+        for bytes in non_minimal {
+            assert_eq!(
+                CompactSize::from_bytes(&bytes),
+                Err(BitcoinError::InvalidFormat)
+            );
+        }
+
+        let tx = BitcoinTransaction::new(1, vec![], vec![], 0);
+        let mut bytes = tx.to_bytes();
+        bytes.splice(4..5, [0xFD, 0x00, 0x00]);
+        assert_eq!(
+            BitcoinTransaction::from_bytes(&bytes),
+            Err(BitcoinError::InvalidFormat)
+        );
+    }
+
+    #[test]
+    fn test_script_rejects_oversized_length() {
+        let mut bytes = vec![0xFF];
+        bytes.extend_from_slice(&(u64::MAX - 3).to_le_bytes());
+        bytes.extend_from_slice(&[0x51; 8]);
+        assert_eq!(
+            Script::from_bytes(&bytes),
+            Err(BitcoinError::InsufficientBytes)
+        );
+
+        let mut bytes = vec![0xFE];
+        bytes.extend_from_slice(&0x0001_0000u32.to_le_bytes());
+        assert_eq!(
+            Script::from_bytes(&bytes),
+            Err(BitcoinError::InsufficientBytes)
+        );
+    }
+
+    #[test]
+    fn test_block_weight_accumulator() {
+        let input = TransactionInput::new(
+            OutPoint::new(dummy_txid(1), 0),
+            Script::new(vec![0x51; 9_000]),
+            0xFFFFFFFF,
+        );
+        let tx = BitcoinTransaction::new(2, vec![input], vec![], 0);
+        let weight = tx.weight();
+
+        let mut accumulator = BlockWeightAccumulator::new();
+        assert_eq!(accumulator.remaining_weight(), MAX_BLOCK_WEIGHT);
+        let mut added = 0;
+        while accumulator.add(&tx).is_ok() {
+            added += 1;
+        }
+        assert_eq!(added, MAX_BLOCK_WEIGHT / weight);
+        assert_eq!(accumulator.weight(), added * weight);
+        assert_eq!(
+            accumulator.remaining_weight(),
+            MAX_BLOCK_WEIGHT - added * weight
+        );
+        assert!(accumulator.remaining_weight() < weight);
+        assert_eq!(accumulator.add(&tx), Err(BitcoinError::LimitExceeded));
+        assert_eq!(accumulator.weight(), added * weight);
+    }
+
+    #[test]
+    fn test_op_return_commitment() {
+        let mut payload = b"FED1".to_vec();
+        payload.extend_from_slice(&[0xAB; 32]);
+        let mut script = Script::new(vec![0x6A]);
+        script.push_data(&payload);
+
+        assert_eq!(script.op_return_data(), Some(&payload[..]));
+        assert_eq!(script.op_return_commitment(b"FED1"), Some(&[0xAB; 32][..]));
+        assert_eq!(script.op_return_commitment(b"FED2"), None);
+
+        let mut witness_commitment = vec![0xAA, 0x21, 0xA9, 0xED];
+        witness_commitment.extend_from_slice(&[0x11; 32]);
+        let mut script = Script::new(vec![0x6A]);
+        script.push_data(&witness_commitment);
+        assert_eq!(
+            script.op_return_commitment(&[0xAA, 0x21, 0xA9, 0xED]),
+            Some(&[0x11; 32][..])
+        );
+
+        let mut not_op_return = Script::new(Vec::new());
+        not_op_return.push_data(&payload);
+        assert_eq!(not_op_return.op_return_commitment(b"FED1"), None);
+        assert_eq!(Script::new(vec![0x6A]).op_return_data(), None);
+    }
+
+    #[test]
+    fn test_decode_hex_lines() {
+        let first = BitcoinTransaction::new(
+            1,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                Script::new(vec![]),
+                0xFFFFFFFF,
+            )],
+            vec![TransactionOutput::new(5_000, p2wpkh_script(1))],
+            0,
+        );
+        let second = BitcoinTransaction::new(2, vec![], vec![], 7);
+        let first_hex = hex::encode(first.to_bytes());
+        let input = format!(
+            "# exported dump\n{}\n\n  {}  \nzz\n{}00\n",
+            first_hex,
+            hex::encode(second.to_bytes()),
+            first_hex
+        );
+
+        let results = decode_hex_lines(&input);
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0], Ok(first));
+        assert_eq!(results[1], Ok(second));
+        assert_eq!(results[2], Err(BitcoinError::InvalidFormat));
+        assert_eq!(results[3], Err(BitcoinError::InvalidFormat));
+    }
+
+    #[test]
+    fn test_output_is_dust() {
+        assert!(TransactionOutput::new(545, p2pkh_script(1)).is_dust());
+        assert!(!TransactionOutput::new(546, p2pkh_script(1)).is_dust());
+        assert!(TransactionOutput::new(293, p2wpkh_script(1)).is_dust());
+        assert!(!TransactionOutput::new(294, p2wpkh_script(1)).is_dust());
+        assert!(!TransactionOutput::new(0, Script::new(vec![0x6A])).is_dust());
+    }
+
+    #[test]
+    fn test_build_spend_with_change() {
+        let utxos = vec![
+            (
+                OutPoint::new(dummy_txid(1), 0),
+                TransactionOutput::new(30_000, p2wpkh_script(1)),
+            ),
+            (
+                OutPoint::new(dummy_txid(2), 1),
+                TransactionOutput::new(50_000, p2wpkh_script(2)),
+            ),
+            (
+                OutPoint::new(dummy_txid(3), 0),
+                TransactionOutput::new(90_000, p2wpkh_script(3)),
+            ),
+        ];
+        let destination = p2pkh_script(9);
+        let change = p2wpkh_script(8);
+
+        let tx = build_spend(&utxos, &destination, 60_000, &change, 10.0).unwrap();
+        assert_eq!(tx.inputs.len(), 2);
+        assert_eq!(tx.outputs.len(), 2);
+        assert_eq!(tx.outputs[0], TransactionOutput::new(60_000, destination));
+        assert_eq!(tx.outputs[1].script_pubkey, change);
+        // Each P2WPKH input adds 68 - 41 vbytes of signing data to the unsigned size.
+        let vsize = tx.vsize() + 2 * (68 - 41);
+        let fee = 80_000 - 60_000 - tx.outputs[1].value;
+        assert_eq!(fee, (vsize as f64 * 10.0).ceil() as u64);
+
+        // Leftover below the dust threshold goes to the fee instead of a change output.
+        let tx = build_spend(&utxos, &p2pkh_script(9), 77_700, &change, 10.0).unwrap();
+        assert_eq!(tx.inputs.len(), 2);
+        assert_eq!(tx.outputs.len(), 1);
+    }
+
+    #[test]
+    fn test_build_spend_insufficient_funds() {
+        let utxos = vec![(
+            OutPoint::new(dummy_txid(1), 0),
+            TransactionOutput::new(10_000, p2wpkh_script(1)),
+        )];
+        assert_eq!(
+            build_spend(&utxos, &p2pkh_script(9), 10_000, &p2wpkh_script(8), 1.0),
+            Err(BitcoinError::InsufficientFunds)
+        );
+        assert_eq!(
+            build_spend(&[], &p2pkh_script(9), 1_000, &p2wpkh_script(8), 1.0),
+            Err(BitcoinError::InsufficientFunds)
+        );
+        assert_eq!(
+            build_spend(&utxos, &p2pkh_script(9), MAX_MONEY, &p2wpkh_script(8), 1.0),
+            Err(BitcoinError::InsufficientFunds)
+        );
+        assert_eq!(
+            build_spend(&utxos, &p2pkh_script(9), 5_000, &p2wpkh_script(8), f64::MAX),
+            Err(BitcoinError::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn test_build_spend_rejects_out_of_range_values() {
+        let utxos = vec![(
+            OutPoint::new(dummy_txid(1), 0),
+            TransactionOutput::new(10_000, p2wpkh_script(1)),
+        )];
+        let change = p2wpkh_script(8);
+        assert_eq!(
+            build_spend(&utxos, &p2pkh_script(9), u64::MAX, &change, 1.0),
+            Err(BitcoinError::ValueOutOfRange)
+        );
+        assert_eq!(
+            build_spend(&utxos, &p2pkh_script(9), 545, &change, 1.0),
+            Err(BitcoinError::ValueOutOfRange)
+        );
+        assert!(build_spend(&utxos, &p2pkh_script(9), 546, &change, 1.0).is_ok());
+        for rate in [-1.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(
+                build_spend(&utxos, &p2pkh_script(9), 5_000, &change, rate),
+                Err(BitcoinError::ValueOutOfRange)
+            );
+        }
+
+        let huge = vec![
+            (
+                OutPoint::new(dummy_txid(1), 0),
+                TransactionOutput::new(u64::MAX, p2wpkh_script(1)),
+            ),
+            (
+                OutPoint::new(dummy_txid(2), 0),
+                TransactionOutput::new(u64::MAX, p2wpkh_script(2)),
+            ),
+        ];
+        assert_eq!(
+            build_spend(&huge, &p2pkh_script(9), MAX_MONEY, &change, 1.0),
+            Err(BitcoinError::ValueOutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_compact_size_is_minimal_encoding() {
+        for value in [0u64, 252, 253, 65535, 65536, 0xFFFFFFFF, 0x1_0000_0000] {
+            assert!(CompactSize::is_minimal_encoding(
+                &CompactSize::new(value).to_bytes()
+            ));
+        }
+        assert!(!CompactSize::is_minimal_encoding(&[0xFD, 0xFC, 0x00]));
+        assert!(!CompactSize::is_minimal_encoding(&[
+            0xFE, 0xFF, 0xFF, 0x00, 0x00
+        ]));
+        assert!(!CompactSize::is_minimal_encoding(&[
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00
+        ]));
+        // Truncated input and trailing bytes are not a single encoded value.
+        assert!(!CompactSize::is_minimal_encoding(&[0xFD, 0x00]));
+        assert!(!CompactSize::is_minimal_encoding(&[0x05, 0x00]));
+        assert!(!CompactSize::is_minimal_encoding(&[]));
+    }
+
+    #[test]
+    fn test_coin_days_destroyed() {
+        let inputs = vec![
+            TransactionInput::new(OutPoint::new(dummy_txid(1), 0), Script::new(vec![]), 0),
+            TransactionInput::new(OutPoint::new(dummy_txid(2), 0), Script::new(vec![]), 0),
+        ];
+        let tx = BitcoinTransaction::new(2, inputs, vec![], 0);
+        let prevouts = vec![
+            // 2 BTC held for 10 days and 0.5 BTC held for 1 day.
+            (
+                TransactionOutput::new(200_000_000, p2wpkh_script(1)),
+                98_560,
+            ),
+            (TransactionOutput::new(50_000_000, p2wpkh_script(2)), 99_856),
+        ];
+        let cdd = tx.coin_days_destroyed(&prevouts, 100_000).unwrap();
+        assert!((cdd - 20.5).abs() < 1e-9);
+
+        assert_eq!(tx.coin_days_destroyed(&prevouts, 90_000), Some(0.0));
+        assert_eq!(tx.coin_days_destroyed(&prevouts[..1], 100_000), None);
+        let mut extra = prevouts.clone();
+        extra.push(prevouts[0].clone());
+        assert_eq!(tx.coin_days_destroyed(&extra, 100_000), None);
+    }
+
+    #[test]
+    fn test_equivalent_ignoring_order() {
+        let inputs = vec![
+            TransactionInput::new(OutPoint::new(dummy_txid(2), 0), Script::new(vec![]), 0),
+            TransactionInput::new(OutPoint::new(dummy_txid(1), 3), Script::new(vec![]), 0),
+        ];
+        let outputs = vec![
+            TransactionOutput::new(9_000, p2wpkh_script(1)),
+            TransactionOutput::new(1_000, p2pkh_script(2)),
+        ];
+        let tx = BitcoinTransaction::new(2, inputs.clone(), outputs.clone(), 0);
+        let reordered = BitcoinTransaction::new(
+            2,
+            inputs.iter().rev().cloned().collect(),
+            outputs.iter().rev().cloned().collect(),
+            0,
+        );
+        assert_ne!(tx, reordered);
+        assert!(tx.equivalent_ignoring_order(&reordered));
+
+        let mut changed = reordered.clone();
+        changed.outputs[0].value += 1;
+        assert!(!tx.equivalent_ignoring_order(&changed));
+
+        let duplicated = BitcoinTransaction::new(
+            2,
+            vec![inputs[0].clone(), inputs[0].clone()],
+            outputs.clone(),
+            0,
+        );
+        assert!(!tx.equivalent_ignoring_order(&duplicated));
+        assert!(!tx.equivalent_ignoring_order(&BitcoinTransaction::new(2, inputs, outputs, 1)));
+    }
+
+    #[cfg(feature = "crc")]
+    #[test]
+    fn test_crc32_changes_with_bytes() {
+        let tx = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                Script::new(vec![]),
+                0xFFFFFFFF,
+            )],
+            vec![TransactionOutput::new(5_000, p2wpkh_script(1))],
+            0,
+        );
+        assert_eq!(tx.crc32(), tx.clone().crc32());
+
+        let mut changed = tx.clone();
+        changed.outputs[0].value += 1;
+        assert_ne!(tx.crc32(), changed.crc32());
+
+        let mut bytes = tx.to_bytes();
+        bytes[0] ^= 0x01;
+        let (flipped, _) = BitcoinTransaction::from_bytes(&bytes).unwrap();
+        assert_ne!(tx.crc32(), flipped.crc32());
+    }
+
+    #[test]
+    fn test_is_dust_consolidation() {
+        let inputs: Vec<TransactionInput> = (1..=4)
+            .map(|i| TransactionInput::new(OutPoint::new(dummy_txid(i), 0), Script::new(vec![]), 0))
+            .collect();
+        let tx = BitcoinTransaction::new(
+            2,
+            inputs.clone(),
+            vec![TransactionOutput::new(3_000, p2wpkh_script(9))],
+            0,
+        );
+        // At 10 sat/vB a P2WPKH output under 980 sats costs more to spend than it holds.
+        let prevouts = vec![
+            TransactionOutput::new(900, p2wpkh_script(1)),
+            TransactionOutput::new(700, p2wpkh_script(2)),
+            TransactionOutput::new(800, p2wpkh_script(3)),
+            TransactionOutput::new(5_000, p2wpkh_script(4)),
+        ];
+        assert!(tx.is_dust_consolidation(&prevouts, 10.0));
+        assert!(!tx.is_dust_consolidation(&prevouts, 1.0));
+        assert!(!tx.is_dust_consolidation(&prevouts[..3], 10.0));
+
+        let fan_out = BitcoinTransaction::new(
+            2,
+            inputs,
+            (0..4)
+                .map(|i| TransactionOutput::new(500, p2wpkh_script(i)))
+                .collect(),
+            0,
+        );
+        assert!(!fan_out.is_dust_consolidation(&prevouts, 10.0));
+    }
+
+    #[test]
+    fn test_parse_headers_message() {
+        let genesis = hex::decode(
+            "0100000000000000000000000000000000000000000000000000000000000000\
+             000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa\
+             4b1e5e4a29ab5f49ffff001d1dac2b7c",
+        )
+        .unwrap();
+        let block_1 = hex::decode(
+            "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d61900\
+             00000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e8\
+             57233e0e61bc6649ffff001d01e36299",
+        )
+        .unwrap();
+        let mut payload = vec![0x02];
+        payload.extend_from_slice(&genesis);
+        payload.push(0x00);
+        payload.extend_from_slice(&block_1);
+        payload.push(0x00);
+
+        let headers = parse_headers_message(&payload).unwrap();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[0].to_bytes(), genesis);
+        assert_eq!(headers[0].time, 1231006505);
+        assert_eq!(headers[0].bits, 0x1d00ffff);
+        assert_eq!(headers[1].nonce, 2573394689);
+        assert_eq!(headers[1].prev_block_hash, headers[0].block_hash());
+        let mut block_1_hash = headers[1].block_hash();
+        block_1_hash.reverse();
+        assert_eq!(
+            hex::encode(block_1_hash),
+            "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048"
+        );
+
+        let mut bad_tx_count = payload.clone();
+        bad_tx_count[81] = 0x01;
+        assert_eq!(
+            parse_headers_message(&bad_tx_count),
+            Err(BitcoinError::InvalidFormat)
+        );
+        assert_eq!(
+            parse_headers_message(&payload[..payload.len() - 1]),
+            Err(BitcoinError::InsufficientBytes)
+        );
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        let timestamps = [
+            1_000, 1_600, 1_200, 1_100, 1_900, 1_300, 1_500, 1_400, 2_000, 1_700, 1_800,
+        ];
+        assert_eq!(median_time_past(&timestamps), Some(1_500));
+
+        // Only the last 11 blocks count, whatever came before them.
+        let mut longer = vec![9_999, 9_999, 9_999];
+        longer.extend_from_slice(&timestamps);
+        assert_eq!(median_time_past(&longer), Some(1_500));
+
+        assert_eq!(median_time_past(&[1_000, 3_000, 2_000, 4_000]), Some(3_000));
+        assert_eq!(median_time_past(&[]), None);
+    }
+
+    #[test]
+    fn test_owned_output_value() {
+        let tx = BitcoinTransaction::new(
+            2,
+            vec![],
+            vec![
+                TransactionOutput::new(40_000, p2wpkh_script(1)),
+                TransactionOutput::new(25_000, p2pkh_script(2)),
+                TransactionOutput::new(7_000, p2wpkh_script(3)),
+            ],
+            0,
+        );
+        let owned: HashSet<Script> = [p2wpkh_script(1), p2wpkh_script(3), p2pkh_script(4)]
+            .into_iter()
+            .collect();
+        assert_eq!(tx.owned_output_value(&owned), 47_000);
+        assert_eq!(tx.owned_output_value(&HashSet::new()), 0);
+
+        let mut huge = tx.clone();
+        huge.outputs[0].value = u64::MAX;
+        assert_eq!(huge.owned_output_value(&owned), u64::MAX);
+    }
+
+    #[test]
+    fn test_spent_owned_value() {
+        let owned_outpoint = OutPoint::new(dummy_txid(1), 0);
+        let tx = BitcoinTransaction::new(
+            2,
+            vec![
+                TransactionInput::new(owned_outpoint.clone(), Script::new(vec![]), 0),
+                TransactionInput::new(OutPoint::new(dummy_txid(2), 0), Script::new(vec![]), 0),
+            ],
+            vec![],
+            0,
+        );
+        let mut owned = HashMap::new();
+        owned.insert(owned_outpoint, 12_000);
+        owned.insert(OutPoint::new(dummy_txid(1), 1), 99_000);
+        assert_eq!(tx.spent_owned_value(&owned), 12_000);
+        assert_eq!(tx.spent_owned_value(&HashMap::new()), 0);
+
+        let both_owned: HashMap<OutPoint, u64> = tx
+            .spent_outpoints()
+            .map(|outpoint| (outpoint.clone(), u64::MAX))
+            .collect();
+        assert_eq!(tx.spent_owned_value(&both_owned), u64::MAX);
+    }
+
+    #[test]
+    fn test_is_cpfp_child_of() {
+        let parent = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                Script::new(vec![]),
+                0xFFFFFFFD,
+            )],
+            vec![
+                TransactionOutput::new(20_000, p2wpkh_script(1)),
+                TransactionOutput::new(5_000, p2wpkh_script(2)),
+            ],
+            0,
+        );
+        let spend = |vout: u32| {
+            BitcoinTransaction::new(
+                2,
+                vec![TransactionInput::new(
+                    OutPoint::new(parent.txid().0, vout),
+                    Script::new(vec![]),
+                    0xFFFFFFFD,
+                )],
+                vec![TransactionOutput::new(4_000, p2wpkh_script(3))],
+                0,
+            )
+        };
+        assert!(spend(1).is_cpfp_child_of(&parent));
+        assert!(!spend(2).is_cpfp_child_of(&parent));
+        assert!(!parent.is_cpfp_child_of(&spend(1)));
+
+        let unrelated = BitcoinTransaction::new(2, vec![], vec![], 0);
+        assert!(!spend(0).is_cpfp_child_of(&unrelated));
+    }
+
+    #[test]
+    fn test_fee_rate_percentiles() {
+        let rates: Vec<f64> = (0..=100).rev().map(|rate| rate as f64).collect();
+        assert_eq!(fee_rate_percentiles(&rates), [10.0, 25.0, 50.0, 75.0, 90.0]);
+        assert_eq!(
+            fee_rate_percentiles(&[3.0, 1.0, 2.0]),
+            [1.0, 1.0, 2.0, 3.0, 3.0]
+        );
+        assert_eq!(
+            fee_rate_percentiles(&[4.0, 1.0, 3.0, 2.0]),
+            [1.0, 1.0, 2.0, 3.0, 4.0]
+        );
+        assert_eq!(fee_rate_percentiles(&[7.5]), [7.5; 5]);
+        assert_eq!(fee_rate_percentiles(&[]), [0.0; 5]);
+    }
+
+    #[test]
+    fn test_validate_package_limits() {
+        let mut chain = Vec::new();
+        let mut previous = OutPoint::new(dummy_txid(1), 0);
+        for _ in 0..26 {
+            let tx = BitcoinTransaction::new(
+                2,
+                vec![TransactionInput::new(
+                    previous,
+                    Script::new(vec![]),
+                    0xFFFFFFFD,
+                )],
+                vec![TransactionOutput::new(1_000, p2wpkh_script(1))],
+                0,
+            );
+            previous = OutPoint::new(tx.txid().0, 0);
+            chain.push(tx);
+        }
+
+        assert_eq!(
+            validate_package_limits(
+                &chain[..25],
+                DEFAULT_ANCESTOR_LIMIT,
+                DEFAULT_ANCESTOR_SIZE_LIMIT_VSIZE
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            validate_package_limits(
+                &chain,
+                DEFAULT_ANCESTOR_LIMIT,
+                DEFAULT_ANCESTOR_SIZE_LIMIT_VSIZE
+            ),
+            Err(BitcoinError::LimitExceeded)
+        );
+
+        let vsize = chain[0].vsize();
+        assert_eq!(validate_package_limits(&chain[..3], 25, 3 * vsize), Ok(()));
+        assert_eq!(
+            validate_package_limits(&chain[..3], 25, 3 * vsize - 1),
+            Err(BitcoinError::LimitExceeded)
+        );
+    }
+
+    #[test]
+    fn test_ancestor_fee_rate() {
+        let funding = OutPoint::new(dummy_txid(1), 0);
+        let parent = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                funding.clone(),
+                Script::new(vec![]),
+                0xFFFFFFFD,
+            )],
+            vec![TransactionOutput::new(99_900, p2wpkh_script(1))],
+            0,
+        );
+        let parent_output = OutPoint::new(parent.txid().0, 0);
+        let child = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                parent_output.clone(),
+                Script::new(vec![]),
+                0xFFFFFFFD,
+            )],
+            vec![TransactionOutput::new(89_900, p2wpkh_script(2))],
+            0,
+        );
+        let unrelated = BitcoinTransaction::new(2, vec![], vec![], 0);
+        let txs = vec![unrelated, parent.clone(), child.clone()];
+        let mut prevouts = HashMap::new();
+        prevouts.insert(funding, 100_000);
+        prevouts.insert(parent_output, 99_900);
+
+        let expected = (100 + 10_000) as f64 / (parent.vsize() + child.vsize()) as f64;
+        assert_eq!(ancestor_fee_rate(&txs, 2, &prevouts), Ok(expected));
+        assert_eq!(
+            ancestor_fee_rate(&txs, 1, &prevouts),
+            Ok(100.0 / parent.vsize() as f64)
+        );
+        assert!(
+            ancestor_fee_rate(&txs, 2, &prevouts).unwrap() < child.fee_rate(&prevouts).unwrap()
+        );
+        assert_eq!(
+            ancestor_fee_rate(&txs, 3, &prevouts),
+            Err(BitcoinError::IndexOutOfBounds)
+        );
+    }
+
+    #[test]
+    fn test_transactions_to_dot() {
+        let parent = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                OutPoint::new(dummy_txid(1), 0),
+                Script::new(vec![]),
+                0xFFFFFFFF,
+            )],
+            vec![
+                TransactionOutput::new(30_000, p2wpkh_script(1)),
+                TransactionOutput::new(20_000, p2wpkh_script(2)),
+            ],
+            0,
+        );
+        let child = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(
+                OutPoint::new(parent.txid().0, 1),
+                Script::new(vec![]),
+                0xFFFFFFFF,
+            )],
+            vec![TransactionOutput::new(19_000, p2wpkh_script(3))],
+            0,
+        );
+
+        let dot = transactions_to_dot(&[parent.clone(), child.clone()], &HashMap::new());
+        assert!(dot.starts_with("digraph transactions {\n"));
+        assert!(dot.contains(&format!(
+            "tx0 [label=\"{}\"];",
+            parent.txid().to_display_string()
+        )));
+        assert!(dot.contains(&format!(
+            "tx1 [label=\"{}\"];",
+            child.txid().to_display_string()
+        )));
+        assert!(dot.contains("tx0 -> tx1 [label=\"20000\"];"));
+        assert_eq!(dot.matches("->").count(), 1);
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_metaprotocol_marker() {
+        let mut runestone = Script::new(vec![0x6A, 0x5D]);
+        runestone.push_data(&[0x14, 0x01]);
+        runestone.push_data(&[0x02, 0x03, 0x04]);
+        assert_eq!(
+            runestone.metaprotocol_marker(),
+            Some(Metaprotocol::Runes(vec![0x14, 0x01, 0x02, 0x03, 0x04]))
+        );
+
+        let mut omni = Script::new(vec![0x6A]);
+        omni.push_data(&[0x6F, 0x6D, 0x6E, 0x69, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F]);
+        assert_eq!(
+            omni.metaprotocol_marker(),
+            Some(Metaprotocol::Omni(vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x1F]))
+        );
+
+        let mut plain = Script::new(vec![0x6A]);
+        plain.push_data(b"hello world");
+        assert_eq!(plain.metaprotocol_marker(), None);
+        assert_eq!(p2wpkh_script(1).metaprotocol_marker(), None);
+    }
+
+    #[test]
+    fn test_sigop_cost() {
+        let keys: Vec<Vec<u8>> = (1u8..=3).map(|i| vec![0x02 + (i % 2); 33]).collect();
+        let redeem_script = multisig_redeem_script(2, &keys).unwrap();
+        assert_eq!(redeem_script.sigop_count(true), 3);
+        assert_eq!(redeem_script.sigop_count(false), 20);
+        assert_eq!(p2pkh_script(1).sigop_count(false), 1);
+
+        let mut script_sig = Script::new(vec![0x00]);
+        script_sig.push_data(&[0x30; 72]);
+        script_sig.push_data(&[0x30; 72]);
+        script_sig.push_data(&redeem_script.bytes);
+        let tx = BitcoinTransaction::new(
+            2,
+            vec![
+                TransactionInput::new(OutPoint::new(dummy_txid(1), 0), script_sig, 0xFFFFFFFF),
+                TransactionInput::new(
+                    OutPoint::new(dummy_txid(2), 0),
+                    Script::new(vec![]),
+                    0xFFFFFFFF,
+                ),
+            ],
+            vec![TransactionOutput::new(1_000, p2pkh_script(9))],
+            0,
+        );
+        let prevouts = vec![
+            TransactionOutput::new(5_000, redeem_script.to_p2sh()),
+            TransactionOutput::new(5_000, p2wpkh_script(2)),
+        ];
+        // One legacy sigop in the output, three in the redeem script, one for P2WPKH.
+        assert_eq!(tx.sigop_cost(&prevouts), 4 + 12 + 1);
+        assert_eq!(tx.sigop_cost(&[]), 4);
+    }
+
+    #[test]
+    fn test_relay_weight_sigop_dominated() {
+        let mut bare_multisig = Script::new(vec![]);
+        for _ in 0..10 {
+            bare_multisig.push_opcode(0xAE);
+        }
+        let tx = BitcoinTransaction::new(
+            2,
+            vec![],
+            vec![TransactionOutput::new(1_000, bare_multisig)],
+            0,
+        );
+        // Ten inaccurate CHECKMULTISIGs: 200 sigops, costed 800, billed 16000 weight.
+        assert_eq!(tx.sigop_cost(&[]), 800);
+        assert!(tx.weight() < 16_000);
+        assert_eq!(tx.relay_weight(&[]), 16_000);
+
+        let plain = BitcoinTransaction::new(
+            2,
+            vec![],
+            vec![TransactionOutput::new(1_000, p2wpkh_script(1))],
+            0,
+        );
+        assert_eq!(plain.relay_weight(&[]), plain.weight());
+    }
+
+    #[test]
+    fn test_check_value_conservation() {
+        let known = OutPoint::new(dummy_txid(1), 0);
+        let tx = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(known.clone(), Script::new(vec![]), 0)],
+            vec![TransactionOutput::new(9_000, p2wpkh_script(1))],
+            0,
+        );
+        let resolve = |outpoint: &OutPoint| (*outpoint == known).then_some(10_000);
+        assert_eq!(tx.check_value_conservation(resolve), Ok(1_000));
+        assert_eq!(
+            tx.check_value_conservation(|_| Some(8_000)),
+            Err(BitcoinError::InsufficientFunds)
+        );
+
+        let mut unknown = tx.clone();
+        unknown.inputs.push(TransactionInput::new(
+            OutPoint::new(dummy_txid(2), 0),
+            Script::new(vec![]),
+            0,
+        ));
+        assert_eq!(
+            unknown.check_value_conservation(resolve),
+            Err(BitcoinError::MissingPrevout)
+        );
+    }
+
+    #[test]
+    fn test_check_value_conservation_rejects_wrapping_values() {
+        let spent = OutPoint::new(dummy_txid(1), 0);
+        let tx = BitcoinTransaction::new(
+            2,
+            vec![TransactionInput::new(spent.clone(), Script::new(vec![]), 0)],
+            vec![
+                TransactionOutput::new(u64::MAX, p2wpkh_script(1)),
+                TransactionOutput::new(2, p2wpkh_script(2)),
+            ],
+            0,
+        );
+        // The outputs sum to 1 with wrapping arithmetic, which would pass for a fee of 9.
+        let (parsed, _) = BitcoinTransaction::from_bytes(&tx.to_bytes()).unwrap();
+        assert_eq!(
+            parsed.check_value_conservation(|_| Some(10)),
+            Err(BitcoinError::ValueOutOfRange)
+        );
+
+        let mut two_inputs = BitcoinTransaction::new(2, vec![], vec![], 0);
+        for vout in 0..2 {
+            two_inputs.inputs.push(TransactionInput::new(
+                OutPoint::new(dummy_txid(1), vout),
+                Script::new(vec![]),
+                0,
+            ));
+        }
+        assert_eq!(
+            two_inputs.check_value_conservation(|_| Some(u64::MAX)),
+            Err(BitcoinError::ValueOutOfRange)
+        );
+        assert_eq!(
+            two_inputs.check_value_conservation(|_| Some(MAX_MONEY / 2 + 1)),
+            Err(BitcoinError::ValueOutOfRange)
+        );
+        assert_eq!(
+            two_inputs.check_value_conservation(|_| Some(MAX_MONEY / 2)),
+            Ok(MAX_MONEY)
+        );
+    }
+
+    #[test]
+    fn test_input_age_spread() {
+        let inputs: Vec<TransactionInput> = (1..=3)
+            .map(|i| TransactionInput::new(OutPoint::new(dummy_txid(i), 0), Script::new(vec![]), 0))
+            .collect();
+        let tx = BitcoinTransaction::new(2, inputs, vec![], 0);
+        assert_eq!(
+            tx.input_age_spread(&[800_000, 799_900, 799_500], 800_100),
+            Some((100, 600, 300.0))
+        );
+        assert_eq!(tx.input_age_spread(&[800_000, 799_990], 800_100), None);
+        assert_eq!(
+            BitcoinTransaction::new(2, vec![], vec![], 0).input_age_spread(&[], 800_100),
+            None
+        );
+    }
+
+    #[test]
+    fn test_sighash_skeleton() {
+        let inputs: Vec<TransactionInput> = (1..=3)
+            .map(|i| {
+                TransactionInput::new(
+                    OutPoint::new(dummy_txid(i), 0),
+                    Script::new(vec![0x51; i as usize]),
+                    0xFFFFFFFD,
+                )
+            })
+            .collect();
+        let outputs: Vec<TransactionOutput> = (1..=2)
+            .map(|i| TransactionOutput::new(1_000 * i as u64, p2wpkh_script(i)))
+            .collect();
+        let tx = BitcoinTransaction::new(2, inputs, outputs, 0);
+
+        let none = tx.sighash_skeleton(1, SIGHASH_NONE).unwrap();
+        let mut expected = tx.skeleton();
+        expected.outputs.clear();
+        expected.inputs[0].sequence = 0;
+        expected.inputs[2].sequence = 0;
+        assert_eq!(none, expected);
+
+        assert_eq!(tx.sighash_skeleton(1, SIGHASH_ALL), Ok(tx.skeleton()));
+
+        let single = tx.sighash_skeleton(1, SIGHASH_SINGLE).unwrap();
+        assert_eq!(single.outputs.len(), 2);
+        assert_eq!(single.outputs[0].value, u64::MAX);
+        assert!(single.outputs[0].script_pubkey.is_empty());
+        assert_eq!(single.outputs[1], tx.outputs[1]);
+        assert_eq!(
+            tx.sighash_skeleton(2, SIGHASH_SINGLE),
+            Err(BitcoinError::IndexOutOfBounds)
+        );
+
+        let anyone = tx
+            .sighash_skeleton(2, SIGHASH_ALL | SIGHASH_ANYONECANPAY)
+            .unwrap();
+        assert_eq!(anyone.inputs, vec![tx.skeleton().inputs[2].clone()]);
+        assert_eq!(anyone.outputs, tx.outputs);
+
+        assert_eq!(
+            tx.sighash_skeleton(3, SIGHASH_ALL),
+            Err(BitcoinError::IndexOutOfBounds)
+        );
+    }
+
+    #[test]
+    fn test_build_sweep() {
+        let utxos = vec![
+            (
+                OutPoint::new(dummy_txid(1), 0),
+                TransactionOutput::new(30_000, p2wpkh_script(1)),
+            ),
+            (
+                OutPoint::new(dummy_txid(2), 1),
+                TransactionOutput::new(20_000, p2pkh_script(2)),
+            ),
+            (
+                OutPoint::new(dummy_txid(3), 0),
+                TransactionOutput::new(10_000, p2wpkh_script(3)),
+            ),
+        ];
+        let destination = p2wpkh_script(9);
+
+        let tx = build_sweep(&utxos, &destination, 5.0).unwrap();
+        assert_eq!(tx.inputs.len(), 3);
+        assert_eq!(tx.outputs.len(), 1);
+        assert_eq!(tx.outputs[0].script_pubkey, destination);
+        // Unsigned size plus signing data: 68 vB per P2WPKH input, 148 for P2PKH.
+        let vsize = tx.vsize() + 2 * (68 - 41) + (148 - 41);
+        let fee = (vsize as f64 * 5.0).ceil() as u64;
+        assert_eq!(tx.outputs[0].value, 60_000 - fee);
+
+        assert_eq!(
+            build_sweep(&utxos, &destination, 200.0),
+            Err(BitcoinError::InsufficientFunds)
+        );
+        assert_eq!(
+            build_sweep(&utxos[2..], &destination, 90.0),
+            Err(BitcoinError::InsufficientFunds)
+        );
+        assert_eq!(
+            build_sweep(&[], &destination, 1.0),
+            Err(BitcoinError::InsufficientFunds)
+        );
+        assert_eq!(
+            build_sweep(&utxos, &destination, f64::MAX),
+            Err(BitcoinError::InsufficientFunds)
+        );
+        for rate in [-5.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(
+                build_sweep(&utxos, &destination, rate),
+                Err(BitcoinError::ValueOutOfRange)
+            );
+        }
+
+        let mut huge = utxos.clone();
+        huge[0].1.value = u64::MAX;
+        huge[1].1.value = u64::MAX;
+        assert_eq!(
+            build_sweep(&huge, &destination, 1.0),
+            Err(BitcoinError::ValueOutOfRange)
+        );
+    }
 }